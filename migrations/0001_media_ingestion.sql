@@ -0,0 +1,33 @@
+ALTER TABLE public.images
+    ADD COLUMN IF NOT EXISTS content_hash TEXT,
+    ADD COLUMN IF NOT EXISTS fetched_at BIGINT;
+
+ALTER TABLE public.audio
+    ADD COLUMN IF NOT EXISTS content_hash TEXT,
+    ADD COLUMN IF NOT EXISTS fetched_at BIGINT;
+
+-- Nothing kept URLs unique before ingestion started upserting on them. Keep
+-- one row per URL (one holding a payload if any) so the indexes below can be
+-- built; no table references images or audio by id yet.
+DELETE FROM public.images i
+USING (
+    SELECT id, first_value(id) OVER (
+        PARTITION BY url ORDER BY (data IS NOT NULL) DESC, id
+    ) AS keep_id
+    FROM public.images
+    WHERE url IS NOT NULL
+) d
+WHERE i.id = d.id AND d.id <> d.keep_id;
+
+DELETE FROM public.audio a
+USING (
+    SELECT id, first_value(id) OVER (
+        PARTITION BY url ORDER BY (data IS NOT NULL) DESC, id
+    ) AS keep_id
+    FROM public.audio
+    WHERE url IS NOT NULL
+) d
+WHERE a.id = d.id AND d.id <> d.keep_id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS images_url_key ON public.images (url);
+CREATE UNIQUE INDEX IF NOT EXISTS audio_url_key ON public.audio (url);
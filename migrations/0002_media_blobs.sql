@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS public.media_blobs (
+    hash TEXT PRIMARY KEY,
+    data BYTEA NOT NULL,
+    size BIGINT NOT NULL,
+    mime TEXT,
+    created_at BIGINT NOT NULL
+);
+
+-- Move payloads already stored inline into the content-addressed table.
+INSERT INTO public.media_blobs (hash, data, size, created_at)
+SELECT encode(sha256(data), 'hex'), data, length(data), extract(epoch FROM now())::bigint
+FROM public.images
+WHERE data IS NOT NULL
+ON CONFLICT (hash) DO NOTHING;
+
+INSERT INTO public.media_blobs (hash, data, size, created_at)
+SELECT encode(sha256(data), 'hex'), data, length(data), extract(epoch FROM now())::bigint
+FROM public.audio
+WHERE data IS NOT NULL
+ON CONFLICT (hash) DO NOTHING;
+
+UPDATE public.images SET content_hash = encode(sha256(data), 'hex'), data = NULL WHERE data IS NOT NULL;
+UPDATE public.audio SET content_hash = encode(sha256(data), 'hex'), data = NULL WHERE data IS NOT NULL;
+
+ALTER TABLE public.images
+    ADD CONSTRAINT images_content_hash_fkey FOREIGN KEY (content_hash) REFERENCES public.media_blobs (hash);
+ALTER TABLE public.audio
+    ADD CONSTRAINT audio_content_hash_fkey FOREIGN KEY (content_hash) REFERENCES public.media_blobs (hash);
+
+CREATE INDEX IF NOT EXISTS images_content_hash_idx ON public.images (content_hash);
+CREATE INDEX IF NOT EXISTS audio_content_hash_idx ON public.audio (content_hash);
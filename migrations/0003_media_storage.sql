@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS public.media_blob_data (
+    hash TEXT PRIMARY KEY,
+    data BYTEA NOT NULL
+);
+
+INSERT INTO public.media_blob_data (hash, data)
+SELECT hash, data FROM public.media_blobs
+ON CONFLICT (hash) DO NOTHING;
+
+ALTER TABLE public.media_blobs
+    DROP COLUMN IF EXISTS data,
+    ADD COLUMN IF NOT EXISTS storage TEXT NOT NULL DEFAULT 'postgres';
+
+CREATE INDEX IF NOT EXISTS media_blobs_storage_idx ON public.media_blobs (storage);
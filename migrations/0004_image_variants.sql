@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS public.image_variants (
+    image_id TEXT NOT NULL REFERENCES public.images (id) ON DELETE CASCADE,
+    variant TEXT NOT NULL,
+    source_hash TEXT NOT NULL,
+    content_hash TEXT NOT NULL REFERENCES public.media_blobs (hash),
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (image_id, variant)
+);
+
+CREATE INDEX IF NOT EXISTS image_variants_content_hash_idx ON public.image_variants (content_hash);
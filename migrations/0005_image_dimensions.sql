@@ -0,0 +1,4 @@
+ALTER TABLE public.images
+    ADD COLUMN IF NOT EXISTS width INTEGER,
+    ADD COLUMN IF NOT EXISTS height INTEGER,
+    ADD COLUMN IF NOT EXISTS format TEXT;
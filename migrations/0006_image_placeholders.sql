@@ -0,0 +1,3 @@
+ALTER TABLE public.images
+    ADD COLUMN IF NOT EXISTS blurhash TEXT,
+    ADD COLUMN IF NOT EXISTS lqip TEXT;
@@ -0,0 +1,10 @@
+ALTER TABLE public.audio
+    ADD COLUMN IF NOT EXISTS duration_ms BIGINT,
+    ADD COLUMN IF NOT EXISTS bitrate INTEGER,
+    ADD COLUMN IF NOT EXISTS sample_rate INTEGER,
+    ADD COLUMN IF NOT EXISTS channels INTEGER,
+    ADD COLUMN IF NOT EXISTS title TEXT,
+    ADD COLUMN IF NOT EXISTS artist TEXT,
+    ADD COLUMN IF NOT EXISTS cover_hash TEXT REFERENCES public.media_blobs (hash);
+
+CREATE INDEX IF NOT EXISTS audio_cover_hash_idx ON public.audio (cover_hash);
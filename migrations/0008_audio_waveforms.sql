@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS public.audio_waveforms (
+    audio_id TEXT PRIMARY KEY REFERENCES public.audio (id) ON DELETE CASCADE,
+    source_hash TEXT NOT NULL,
+    sample_rate INTEGER NOT NULL,
+    samples_per_point INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    created_at BIGINT NOT NULL
+);
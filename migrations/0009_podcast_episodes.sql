@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS public.episodes (
+    id TEXT PRIMARY KEY,
+    audio_id TEXT NOT NULL REFERENCES public.audio (id),
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    season INTEGER,
+    episode INTEGER,
+    episode_type TEXT NOT NULL DEFAULT 'full' CHECK (episode_type IN ('full', 'trailer', 'bonus')),
+    explicit BOOLEAN NOT NULL DEFAULT FALSE,
+    artwork_url TEXT,
+    published_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS episodes_published_at_idx ON public.episodes (published_at DESC);
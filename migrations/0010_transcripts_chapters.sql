@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS public.audio_transcripts (
+    audio_id TEXT PRIMARY KEY REFERENCES public.audio (id) ON DELETE CASCADE,
+    language TEXT NOT NULL,
+    cues JSONB NOT NULL,
+    body TEXT NOT NULL,
+    search TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', body)) STORED,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audio_transcripts_search_idx ON public.audio_transcripts USING gin (search);
+
+CREATE TABLE IF NOT EXISTS public.audio_chapters (
+    audio_id TEXT PRIMARY KEY REFERENCES public.audio (id) ON DELETE CASCADE,
+    chapters JSONB NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS articles_search_idx ON public.articles USING gin (
+    to_tsvector('simple', coalesce(title, '') || ' ' || coalesce(subheading, '') || ' ' || coalesce(content, ''))
+);
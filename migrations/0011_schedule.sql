@@ -0,0 +1,50 @@
+CREATE TABLE IF NOT EXISTS public.hosts (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    bio TEXT,
+    image_url TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS public.shows (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    description TEXT,
+    image_url TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS public.show_hosts (
+    show_id TEXT NOT NULL REFERENCES public.shows (id) ON DELETE CASCADE,
+    host_id TEXT NOT NULL REFERENCES public.hosts (id) ON DELETE CASCADE,
+    PRIMARY KEY (show_id, host_id)
+);
+
+-- Recurring weekly slots, expressed in the slot's own time zone so they follow DST.
+CREATE TABLE IF NOT EXISTS public.schedule_slots (
+    id TEXT PRIMARY KEY,
+    show_id TEXT NOT NULL REFERENCES public.shows (id) ON DELETE CASCADE,
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 0 AND 6),
+    start_minute INTEGER NOT NULL CHECK (start_minute BETWEEN 0 AND 1439),
+    duration_minutes INTEGER NOT NULL CHECK (duration_minutes > 0),
+    timezone TEXT NOT NULL,
+    valid_from BIGINT,
+    valid_until BIGINT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS schedule_slots_show_id_idx ON public.schedule_slots (show_id);
+
+-- One-off changes: anything recurring inside [starts_at, ends_at) is replaced by show_id, or by nothing when it is NULL.
+CREATE TABLE IF NOT EXISTS public.schedule_overrides (
+    id TEXT PRIMARY KEY,
+    show_id TEXT REFERENCES public.shows (id) ON DELETE CASCADE,
+    starts_at BIGINT NOT NULL,
+    ends_at BIGINT NOT NULL CHECK (ends_at > starts_at),
+    note TEXT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS schedule_overrides_range_idx ON public.schedule_overrides (starts_at, ends_at);
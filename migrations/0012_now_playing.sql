@@ -0,0 +1,22 @@
+-- Single-row snapshot of what the stream is playing right now.
+CREATE TABLE IF NOT EXISTS public.now_playing (
+    id SMALLINT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    title TEXT,
+    artist TEXT,
+    show_id TEXT REFERENCES public.shows (id) ON DELETE SET NULL,
+    listeners INTEGER,
+    started_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS public.play_history (
+    id BIGSERIAL PRIMARY KEY,
+    title TEXT,
+    artist TEXT,
+    show_id TEXT REFERENCES public.shows (id) ON DELETE SET NULL,
+    started_at BIGINT NOT NULL,
+    ended_at BIGINT,
+    peak_listeners INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS play_history_started_at_idx ON public.play_history (started_at DESC);
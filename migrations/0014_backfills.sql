@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS public.backfills (
+    id TEXT PRIMARY KEY,
+    status TEXT NOT NULL CHECK (status IN ('running', 'completed', 'interrupted')),
+    kinds TEXT[] NOT NULL,
+    concurrency INTEGER NOT NULL,
+    total INTEGER NOT NULL DEFAULT 0,
+    succeeded INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    started_at BIGINT NOT NULL,
+    finished_at BIGINT,
+    updated_at BIGINT NOT NULL
+);
+
+-- At most one backfill runs at a time.
+CREATE UNIQUE INDEX IF NOT EXISTS backfills_one_running ON public.backfills ((TRUE)) WHERE status = 'running';
+
+-- Rows a backfill could not ingest; a resumed backfill skips them.
+CREATE TABLE IF NOT EXISTS public.backfill_failures (
+    backfill_id TEXT NOT NULL REFERENCES public.backfills (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    row_id TEXT NOT NULL,
+    url TEXT,
+    status INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    error TEXT NOT NULL,
+    PRIMARY KEY (backfill_id, kind, row_id)
+);
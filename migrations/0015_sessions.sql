@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS public.sessions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES public.users (id) ON DELETE CASCADE,
+    -- SHA-256 of the secret half of the refresh cookie; the cookie itself is never stored.
+    refresh_hash TEXT NOT NULL,
+    user_agent TEXT,
+    ip TEXT,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON public.sessions (user_id);
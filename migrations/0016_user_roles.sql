@@ -0,0 +1,3 @@
+ALTER TABLE public.users
+    ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'reader'
+        CHECK (role IN ('admin', 'editor', 'author', 'reader'));
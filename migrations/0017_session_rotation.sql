@@ -0,0 +1,11 @@
+ALTER TABLE public.sessions ADD COLUMN IF NOT EXISTS revoked_reason TEXT;
+
+-- Refresh secrets a session has rotated away from. Presenting one again means
+-- the cookie was copied, so the whole session is revoked.
+CREATE TABLE IF NOT EXISTS public.session_retired_tokens (
+    refresh_hash TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL REFERENCES public.sessions (id) ON DELETE CASCADE,
+    retired_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS session_retired_tokens_session_id_idx ON public.session_retired_tokens (session_id);
@@ -0,0 +1,65 @@
+-- A user can sign in through several identities; each auth row now names its user.
+-- users.auth_id stays as the user's primary identity.
+ALTER TABLE public.auth ADD COLUMN IF NOT EXISTS user_id TEXT REFERENCES public.users (id) ON DELETE CASCADE;
+ALTER TABLE public.auth ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE public.auth a SET user_id = u.id
+FROM public.users u
+WHERE u.auth_id = a.id AND a.user_id IS NULL;
+
+-- Concurrent first sign-ins could store one identity twice. Keep the row a
+-- user is attached to (the most recently used if several), point users at it,
+-- and drop the rest so the unique index below can be built.
+CREATE TEMP TABLE auth_duplicates AS
+SELECT id, keep_id FROM (
+    SELECT id, first_value(id) OVER (
+        PARTITION BY provider, sub
+        ORDER BY (user_id IS NOT NULL) DESC, last_sign_in_at DESC, created_at, id
+    ) AS keep_id
+    FROM public.auth
+) ranked
+WHERE id <> keep_id;
+
+UPDATE public.users u SET auth_id = d.keep_id
+FROM auth_duplicates d
+WHERE u.auth_id = d.id;
+
+UPDATE public.auth k SET last_sign_in_at = GREATEST(k.last_sign_in_at, m.last_sign_in_at)
+FROM (
+    SELECT d.keep_id, max(a.last_sign_in_at) AS last_sign_in_at
+    FROM auth_duplicates d JOIN public.auth a ON a.id = d.id
+    GROUP BY d.keep_id
+) m
+WHERE k.id = m.keep_id;
+
+DELETE FROM public.auth a USING auth_duplicates d WHERE a.id = d.id;
+DROP TABLE auth_duplicates;
+
+-- Each copy of a duplicated identity usually created its own user, and the users
+-- whose copy was dropped now point at a row another user owns. They are one
+-- person, so fold each into that user: articles and sessions move over, the
+-- higher role is kept along with the survivor's profile, and the rest is deleted.
+CREATE TEMP TABLE user_merges AS
+SELECT u.id AS from_id, a.user_id AS into_id
+FROM public.users u
+JOIN public.auth a ON a.id = u.auth_id
+WHERE a.user_id IS NOT NULL AND a.user_id <> u.id;
+
+UPDATE public.articles t SET authorid = m.into_id FROM user_merges m WHERE t.authorid = m.from_id;
+UPDATE public.sessions s SET user_id = m.into_id FROM user_merges m WHERE s.user_id = m.from_id;
+UPDATE public.auth a SET user_id = m.into_id FROM user_merges m WHERE a.user_id = m.from_id;
+
+UPDATE public.users k SET role = (ARRAY['reader', 'author', 'editor', 'admin'])[r.rank]
+FROM (
+    SELECT m.into_id, max(array_position(ARRAY['reader', 'author', 'editor', 'admin'], f.role)) AS rank
+    FROM user_merges m JOIN public.users f ON f.id = m.from_id
+    GROUP BY m.into_id
+) r
+WHERE k.id = r.into_id AND r.rank > array_position(ARRAY['reader', 'author', 'editor', 'admin'], k.role);
+
+DELETE FROM public.users u USING user_merges m WHERE u.id = m.from_id;
+DROP TABLE user_merges;
+
+CREATE UNIQUE INDEX IF NOT EXISTS auth_provider_sub_idx ON public.auth (provider, sub);
+CREATE INDEX IF NOT EXISTS auth_user_id_idx ON public.auth (user_id);
+CREATE INDEX IF NOT EXISTS auth_email_idx ON public.auth (lower(email)) WHERE email_verified;
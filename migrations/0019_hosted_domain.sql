@@ -0,0 +1,2 @@
+-- Google Workspace domain (`hd` claim) the identity signed in from, if any.
+ALTER TABLE public.auth ADD COLUMN IF NOT EXISTS hosted_domain TEXT;
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS public.api_keys (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- Requests made with the key act as this user.
+    user_id TEXT NOT NULL REFERENCES public.users (id) ON DELETE CASCADE,
+    -- Public part of the key, shown in listings to tell keys apart.
+    prefix TEXT NOT NULL UNIQUE,
+    -- SHA-256 of the whole key; the key itself is only shown once, when created.
+    key_hash TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_by TEXT REFERENCES public.users (id) ON DELETE SET NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT,
+    last_used_at BIGINT,
+    revoked_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_user_id_idx ON public.api_keys (user_id);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS public.magic_links (
+    id TEXT PRIMARY KEY,
+    email TEXT NOT NULL,
+    -- SHA-256 of the token in the emailed link.
+    token_hash TEXT NOT NULL UNIQUE,
+    ip TEXT,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS magic_links_email_idx ON public.magic_links (lower(email), created_at);
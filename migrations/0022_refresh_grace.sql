@@ -0,0 +1,5 @@
+-- The secret a session last rotated away from, and when. A refresh that raced
+-- the rotation (another tab, a retried request) presents it shortly after.
+ALTER TABLE public.sessions
+    ADD COLUMN IF NOT EXISTS previous_hash TEXT,
+    ADD COLUMN IF NOT EXISTS rotated_at BIGINT;
@@ -0,0 +1,4 @@
+-- Only Google's `hd` claim says who manages an account; forget any recorded
+-- from other providers.
+UPDATE public.auth SET hosted_domain = NULL
+WHERE provider <> 'Google' AND hosted_domain IS NOT NULL;
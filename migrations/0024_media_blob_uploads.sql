@@ -0,0 +1,11 @@
+-- Payloads written to a backend outside the database (fs, s3) before their
+-- index entry commits. Garbage collection purges the ones whose ingest rolled
+-- back and forgets the rest.
+CREATE TABLE IF NOT EXISTS public.media_blob_uploads (
+    hash TEXT NOT NULL,
+    storage TEXT NOT NULL,
+    started_at BIGINT NOT NULL,
+    PRIMARY KEY (hash, storage)
+);
+
+CREATE INDEX IF NOT EXISTS media_blob_uploads_started_at_idx ON public.media_blob_uploads (started_at);
@@ -0,0 +1,121 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::api_key;
+use crate::session::AuthUser;
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyData {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// User the key acts as; defaults to the caller.
+    pub user_id: Option<String>,
+    /// Lifetime in days; the key never expires if omitted.
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub id: String,
+    pub name: String,
+    pub user_id: String,
+    pub prefix: String,
+    /// The full key. It is not stored and cannot be shown again.
+    pub key: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// Creates an API key. The response is the only time the key itself is returned.
+pub async fn create(
+    Extension(pool): Extension<PgPool>,
+    admin: AuthUser,
+    Json(payload): Json<ApiKeyData>,
+) -> Result<Json<CreatedApiKey>, (StatusCode, String)> {
+    let name = payload.name.trim().to_string();
+    if name.is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Name is required".into()));
+    }
+    if payload.scopes.is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "At least one scope is required".into()));
+    }
+    if let Some(unknown) = payload.scopes.iter().find(|scope| !api_key::is_scope(scope)) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown scope: {}", unknown)));
+    }
+    if payload.expires_in_days.is_some_and(|days| days <= 0) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "expires_in_days must be positive".into()));
+    }
+
+    let user_id = payload.user_id.unwrap_or_else(|| admin.user_id.clone());
+    let mut scopes = payload.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    let now = Utc::now().timestamp();
+    let expires_at = payload.expires_in_days.map(|days| now + days.saturating_mul(24 * 3600));
+    let id = Uuid::new_v4().to_string();
+    let new_key = api_key::generate();
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO public.api_keys (id, name, user_id, prefix, key_hash, scopes, created_by, created_at, expires_at)
+        SELECT $1, $2, u.id, $4, $5, $6, $7, $8, $9
+        FROM public.users u
+        WHERE u.id = $3
+        "#,
+        id,
+        name,
+        user_id,
+        new_key.prefix,
+        new_key.hash,
+        &scopes,
+        admin.user_id,
+        now,
+        expires_at,
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Insert failed: {}", e)))?;
+
+    if inserted.rows_affected() == 0 {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown user: {}", user_id)));
+    }
+
+    Ok(Json(CreatedApiKey {
+        id,
+        name,
+        user_id,
+        prefix: new_key.prefix,
+        key: new_key.key,
+        scopes,
+        created_at: now,
+        expires_at,
+    }))
+}
+
+pub async fn revoke(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE public.api_keys SET revoked_at = $2
+        WHERE id = $1 AND revoked_at IS NULL
+        "#,
+        id,
+        Utc::now().timestamp(),
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Revoke failed: {}", e)))?;
+
+    if revoked.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "API key not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,37 @@
+use axum::{Extension, Json};
+use axum::http::StatusCode;
+use serde::Serialize;
+use sqlx::PgPool;
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    pub user_id: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_by: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+/// Every API key, newest first. Keys themselves are never returned.
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<ApiKeyResponse>>, (StatusCode, String)> {
+    let keys = sqlx::query_as!(
+        ApiKeyResponse,
+        r#"
+        SELECT id, name, user_id, prefix, scopes, created_by, created_at, expires_at, last_used_at, revoked_at
+        FROM public.api_keys
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?;
+
+    Ok(Json(keys))
+}
@@ -3,12 +3,17 @@ use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
+use crate::api::image_selector::{embedded_images, ImageMeta};
+use crate::authz::Role;
+use crate::events::{ArticlePublished, Event, EventBus};
+use crate::session::AuthUser;
 
 #[derive(Debug, Deserialize)]
 pub struct ArticleData {
     pub title: String,
     pub content: String,
-    pub authorid: String,
+    /// Defaults to the signed-in user; only editors may name someone else.
+    pub authorid: Option<String>,
     pub ispublished: String,
     pub language: String,
     pub readtime: String,
@@ -37,10 +42,49 @@ pub struct ArticleResponse {
     pub mobiletitle: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ArticleWithImages {
+    #[serde(flatten)]
+    pub article: ArticleResponse,
+    pub images: Vec<ImageMeta>,
+}
+
+/// The article flags are stored as strings; `1` and `true` both mean set.
+fn flag(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+/// Resolves who an article is credited to: the caller unless an editor names another existing user.
+async fn resolve_author(pool: &PgPool, user: &AuthUser, requested: Option<String>) -> Result<String, (StatusCode, String)> {
+    let Some(authorid) = requested.filter(|id| *id != user.user_id) else {
+        return Ok(user.user_id.clone());
+    };
+    if user.role < Role::Editor {
+        return Err((StatusCode::FORBIDDEN, "Only editors may publish as another author".into()));
+    }
+
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM public.users WHERE id = $1) AS "exists!"
+        "#,
+        authorid
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Author lookup failed: {}", e)))?;
+
+    if !exists {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown author: {}", authorid)));
+    }
+    Ok(authorid)
+}
+
 pub async fn handler(
     Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    user: AuthUser,
     Json(payload): Json<ArticleData>,
-) -> Result<Json<ArticleResponse>, (StatusCode, String)> {
+) -> Result<Json<ArticleWithImages>, (StatusCode, String)> {
     let new_id = Uuid::new_v4().to_string();
 
     let ArticleData {
@@ -58,6 +102,8 @@ pub async fn handler(
         mobiletitle,
     } = payload;
 
+    let authorid = resolve_author(&pool, &user, authorid).await?;
+
     sqlx::query!(
         r#"
         INSERT INTO public.articles (id, title, content, authorid, ispublished, language, readtime, subheading, isarchived, realtitle, ismainpage, isurltitledifferent, mobiletitle)
@@ -107,5 +153,110 @@ pub async fn handler(
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?;
 
-    Ok(Json(row))
+    let images = embedded_images(&pool, row.content.as_deref().unwrap_or_default())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Image lookup failed: {}", e)))?;
+
+    if flag(row.ispublished.as_deref()) && !flag(row.isarchived.as_deref()) {
+        bus.publish(Event::ArticlePublished(ArticlePublished {
+            id: new_id,
+            title: row.title.clone().unwrap_or_default(),
+            subheading: row.subheading.clone().unwrap_or_default(),
+            language: row.language.clone().unwrap_or_default(),
+            ismainpage: flag(row.ismainpage.as_deref()),
+        }));
+    }
+
+    Ok(Json(ArticleWithImages { article: row, images }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::FromRequestParts;
+    use axum::http::{header, Request};
+    use crate::api_key;
+    use crate::db::test_pool;
+    use crate::session::Credential;
+
+    async fn user(pool: &PgPool, role: &str) -> String {
+        sqlx::query_scalar!("INSERT INTO public.users (role) VALUES ($1) RETURNING id", role)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    fn signed_in(user_id: &str, role: Role) -> AuthUser {
+        AuthUser { user_id: user_id.to_string(), role, credential: Credential::Session("session".into()) }
+    }
+
+    async fn remove(pool: &PgPool, users: &[String]) {
+        sqlx::query!("DELETE FROM public.users WHERE id = ANY($1)", users)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn authors_publish_only_as_themselves() {
+        let pool = test_pool().await;
+        let (author, other) = (user(&pool, "author").await, user(&pool, "author").await);
+        let caller = signed_in(&author, Role::Author);
+
+        assert_eq!(resolve_author(&pool, &caller, None).await.unwrap(), author);
+        assert_eq!(resolve_author(&pool, &caller, Some(author.clone())).await.unwrap(), author);
+        let (status, _) = resolve_author(&pool, &caller, Some(other.clone())).await.unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        remove(&pool, &[author, other]).await;
+    }
+
+    #[tokio::test]
+    async fn editors_credit_other_existing_users() {
+        let pool = test_pool().await;
+        let (editor, author) = (user(&pool, "editor").await, user(&pool, "author").await);
+        let caller = signed_in(&editor, Role::Editor);
+
+        assert_eq!(resolve_author(&pool, &caller, None).await.unwrap(), editor);
+        assert_eq!(resolve_author(&pool, &caller, Some(author.clone())).await.unwrap(), author);
+        let (status, _) = resolve_author(&pool, &caller, Some(Uuid::new_v4().to_string())).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        remove(&pool, &[editor, author]).await;
+    }
+
+    #[tokio::test]
+    async fn api_keys_publish_as_their_owner() {
+        let pool = test_pool().await;
+        let (owner, other) = (user(&pool, "author").await, user(&pool, "author").await);
+        let key = api_key::generate();
+        sqlx::query!(
+            r#"
+            INSERT INTO public.api_keys (id, name, user_id, prefix, key_hash, scopes, created_at)
+            VALUES ($1, 'scraper', $2, $3, $4, ARRAY['articles:write'], 0)
+            "#,
+            Uuid::new_v4().to_string(),
+            owner,
+            key.prefix,
+            key.hash,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let (mut parts, ()) = Request::post("/article")
+            .header(header::AUTHORIZATION, format!("Bearer {}", key.key))
+            .extension(pool.clone())
+            .body(())
+            .unwrap()
+            .into_parts();
+        let caller = AuthUser::from_request_parts(&mut parts, &()).await.unwrap();
+        assert!(caller.session_id().is_none());
+
+        assert_eq!(resolve_author(&pool, &caller, None).await.unwrap(), owner);
+        let (status, _) = resolve_author(&pool, &caller, Some(other.clone())).await.unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        remove(&pool, &[owner, other]).await;
+    }
 }
@@ -1,8 +1,17 @@
 use axum::{Extension, Json};
 use axum::http::StatusCode;
+use axum::response::Response;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use reqwest::Client;
+use uuid::Uuid;
+use crate::api::job_selector::accept;
+use crate::jobs;
+use crate::media::audio_meta::{probe, AudioMeta};
+use crate::media::blobs;
+use crate::media::fetch::{content_hash, fetch, MediaKind};
+use crate::media::storage::SharedStorage;
+use crate::media::waveform;
 
 #[derive(Debug, Deserialize)]
 pub struct AudioData {
@@ -14,43 +23,200 @@ pub struct AudioResponse {
     pub id: Option<String>,
     pub url: Option<String>,
     pub is_indb: Option<i32>,
+    pub content_hash: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub cover_hash: Option<String>,
+    pub created: bool,
+    pub changed: bool,
 }
 
+/// Queues ingestion of `url`; the job runs [`ingest`] in the background.
 pub async fn handler(
     Extension(pool): Extension<PgPool>,
     Json(payload): Json<AudioData>,
-) -> Result<Json<AudioResponse>, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
+    accept(&pool, jobs::INGEST_AUDIO, &payload.url).await
+}
 
-    let client = Client::new();
-    let resp = client
-        .get(&payload.url)
-        .send()
-        .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Download failed: {}", e)))?;
+/// Downloads and stores `url`, replacing whatever was stored for it before.
+pub async fn ingest(pool: &PgPool, storage: &SharedStorage, url: &str) -> Result<AudioResponse, (StatusCode, String)> {
+    let fetched = fetch(url, MediaKind::Audio).await?;
 
-    if !resp.status().is_success() {
-        return Err((StatusCode::BAD_REQUEST, format!("Failed to fetch image: HTTP {}", resp.status())));
-    }
+    let bytes = fetched.bytes.to_vec();
+    let mime = fetched.mime.clone();
+    let source = url.to_string();
+    let (mut meta, peaks) = tokio::task::spawn_blocking(move || {
+        let meta = probe(bytes.clone(), mime.as_deref()).unwrap_or_else(|e| {
+            tracing::warn!("Could not read audio metadata for {}: {}", source, e);
+            AudioMeta::default()
+        });
+        let peaks = waveform::generate(bytes, mime.as_deref())
+            .inspect_err(|e| tracing::warn!("Could not generate waveform for {}: {}", source, e))
+            .ok();
+        (meta, peaks)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Audio analysis task failed: {}", e)))?;
+
+    let cover = meta.cover.take();
+    let cover_hash = cover.as_ref().map(|cover| content_hash(&cover.data));
+
+    // Garbage collection can remove a blob between `store` finding it and the
+    // upsert referencing it, which then fails on the foreign key; the next attempt
+    // stores it again.
+    let mut attempt = 1;
+    let (row, released) = loop {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Transaction failed: {}", e)))?;
+
+        blobs::store(pool, &mut tx, storage.as_ref(), &fetched.hash, &fetched.bytes, fetched.mime.as_deref())
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+        if let (Some(cover), Some(hash)) = (&cover, &cover_hash) {
+            blobs::store(pool, &mut tx, storage.as_ref(), hash, &cover.data, Some(&cover.mime))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        }
+
+        let now = Utc::now().timestamp();
 
-    let bytes = resp
-        .bytes()
+        // `created` comes from the upsert itself, so concurrent first ingests of a
+        // URL cannot both report it as new.
+        let row = match sqlx::query!(
+            r#"
+            WITH previous AS (
+                SELECT content_hash, cover_hash FROM public.audio
+                WHERE url = $2
+            )
+            INSERT INTO public.audio (id, url, is_indb, content_hash, fetched_at, duration_ms, bitrate, sample_rate, channels, title, artist, cover_hash)
+            VALUES ($1, $2, 1, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (url) DO UPDATE
+            SET data = NULL,
+                is_indb = 1,
+                content_hash = EXCLUDED.content_hash,
+                fetched_at = EXCLUDED.fetched_at,
+                duration_ms = EXCLUDED.duration_ms,
+                bitrate = EXCLUDED.bitrate,
+                sample_rate = EXCLUDED.sample_rate,
+                channels = EXCLUDED.channels,
+                title = EXCLUDED.title,
+                artist = EXCLUDED.artist,
+                cover_hash = EXCLUDED.cover_hash
+            RETURNING id, url, is_indb, content_hash, duration_ms, bitrate, sample_rate, channels, title, artist, cover_hash,
+                (xmax = 0) AS "created!",
+                (SELECT content_hash FROM previous) AS previous_hash,
+                (SELECT cover_hash FROM previous) AS previous_cover
+            "#,
+            Uuid::new_v4().to_string(),
+            url,
+            fetched.hash,
+            now,
+            meta.duration_ms,
+            meta.bitrate,
+            meta.sample_rate,
+            meta.channels,
+            meta.title,
+            meta.artist,
+            cover_hash,
+        )
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Read bytes failed: {}", e)))?;
-
-    let updated = sqlx::query_as!(
-        AudioResponse,
-        r#"
-        UPDATE public.audio
-        SET data = $1, is_indb = 1
-        WHERE url = $2
-        RETURNING id, url, is_indb
-        "#,
-        bytes.as_ref(),
-        payload.url
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Update failed: {}", e)))?;
+        {
+            Ok(row) => row,
+            Err(e) if blobs::collected(&e) && attempt < blobs::STORE_ATTEMPTS => {
+                tracing::warn!("A blob was collected while ingesting {}, storing it again", url);
+                attempt += 1;
+                continue;
+            }
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Upsert failed: {}", e))),
+        };
+
+        match &peaks {
+            Some(peaks) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO public.audio_waveforms (audio_id, source_hash, sample_rate, samples_per_point, data, created_at)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (audio_id) DO UPDATE
+                    SET source_hash = EXCLUDED.source_hash,
+                        sample_rate = EXCLUDED.sample_rate,
+                        samples_per_point = EXCLUDED.samples_per_point,
+                        data = EXCLUDED.data,
+                        created_at = EXCLUDED.created_at
+                    "#,
+                    row.id,
+                    fetched.hash,
+                    peaks.sample_rate as i32,
+                    peaks.samples_per_point as i32,
+                    peaks.data.iter().map(|v| *v as u8).collect::<Vec<u8>>(),
+                    now,
+                )
+                .execute(&mut *tx)
+                .await
+            }
+            None => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM public.audio_waveforms
+                    WHERE audio_id = $1
+                    "#,
+                    row.id
+                )
+                .execute(&mut *tx)
+                .await
+            }
+        }
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Waveform update failed: {}", e)))?;
+
+        let mut released = Vec::new();
+        for (old, new) in [(&row.previous_hash, Some(&fetched.hash)), (&row.previous_cover, cover_hash.as_ref())] {
+            if let Some(old) = old
+                && Some(old) != new
+                && let Some(backend) = blobs::release(&mut tx, old)
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+            {
+                released.push((old.clone(), backend));
+            }
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Commit failed: {}", e)))?;
+
+        break (row, released);
+    };
+
+    let created = row.created;
+    let old_hash = row.previous_hash;
+
+    for (hash, backend) in released {
+        if let Err(e) = blobs::purge(pool, &hash, &backend).await {
+            tracing::warn!("Failed to purge released blob {}: {}", hash, e);
+        }
+    }
 
-    Ok(Json(updated))
+    Ok(AudioResponse {
+        id: Some(row.id),
+        url: row.url,
+        is_indb: row.is_indb,
+        content_hash: row.content_hash,
+        duration_ms: row.duration_ms,
+        bitrate: row.bitrate,
+        sample_rate: row.sample_rate,
+        channels: row.channels,
+        title: row.title,
+        artist: row.artist,
+        cover_hash: row.cover_hash,
+        created,
+        changed: old_hash.as_deref() != Some(fetched.hash.as_str()),
+    })
 }
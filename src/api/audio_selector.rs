@@ -0,0 +1,244 @@
+use axum::Extension;
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::media::blobs;
+use crate::media::waveform::{Waveform, STORED_POINTS};
+
+/// Parses a single `bytes=start-end` range, returning `None` if it cannot be satisfied.
+fn parse_range(value: &str, len: usize) -> Option<(usize, usize)> {
+    let spec = value.strip_prefix("bytes=")?;
+    if len == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().ok()?;
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<usize>().ok()?.min(len - 1)),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+/// Streams the stored audio file. Supports byte ranges, which podcast apps
+/// rely on for seeking and resumable downloads.
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let audio = sqlx::query!(
+        r#"
+        SELECT a.content_hash AS "content_hash?", b.mime AS "mime?", b.size AS "size?"
+        FROM public.audio a
+        LEFT JOIN public.media_blobs b ON b.hash = a.content_hash
+        WHERE a.id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "Audio not found".into()))?;
+
+    let hash = audio
+        .content_hash
+        .ok_or((StatusCode::NOT_FOUND, "Audio has not been ingested yet".into()))?;
+    let missing = || (StatusCode::NOT_FOUND, "Audio payload is missing".to_string());
+    let len = audio.size.ok_or_else(missing)? as usize;
+
+    let mime = audio.mime.unwrap_or_else(|| "application/octet-stream".into());
+    let etag = format!("\"{}\"", hash);
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        // Multi-range requests are rare enough to just get the whole file.
+        .filter(|v| !v.contains(','))
+        .map(|v| parse_range(v, len));
+
+    match range {
+        // Seeking players ask for small pieces of long files; only read those.
+        Some(Some((start, end))) => {
+            let data = blobs::load_range(&pool, &hash, start as u64, end as u64)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+                .ok_or_else(missing)?;
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    (header::CONTENT_TYPE, mime),
+                    (header::ACCEPT_RANGES, "bytes".into()),
+                    (header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len)),
+                    (header::ETAG, etag),
+                ],
+                data,
+            )
+                .into_response())
+        }
+        Some(None) => Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+        )
+            .into_response()),
+        None => {
+            let data = blobs::load(&pool, &hash)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+                .ok_or_else(missing)?;
+            Ok((
+                [
+                    (header::CONTENT_TYPE, mime),
+                    (header::ACCEPT_RANGES, "bytes".into()),
+                    (header::ETAG, etag),
+                ],
+                data,
+            )
+                .into_response())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaveformQuery {
+    pub points: Option<usize>,
+    pub format: Option<String>,
+}
+
+/// Peaks in the JSON layout produced by BBC's `audiowaveform`, which
+/// peaks.js and wavesurfer.js read directly.
+#[derive(Debug, Serialize)]
+pub struct WaveformResponse {
+    pub version: u32,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    pub bits: u32,
+    pub length: usize,
+    pub data: Vec<i8>,
+}
+
+pub async fn waveform(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    Query(query): Query<WaveformQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let row = sqlx::query!(
+        r#"
+        SELECT w.sample_rate, w.samples_per_point, w.data
+        FROM public.audio_waveforms w
+        JOIN public.audio a ON a.id = w.audio_id
+        WHERE w.audio_id = $1 AND a.content_hash = w.source_hash
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "No waveform for this audio".into()))?;
+
+    let stored = Waveform {
+        sample_rate: row.sample_rate as u32,
+        samples_per_point: row.samples_per_point as u32,
+        data: row.data.into_iter().map(|v| v as i8).collect(),
+    };
+    let peaks = stored.resample(query.points.unwrap_or(STORED_POINTS));
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok(Json(WaveformResponse {
+            version: 2,
+            channels: 1,
+            sample_rate: peaks.sample_rate,
+            samples_per_pixel: peaks.samples_per_point,
+            bits: 8,
+            length: peaks.points(),
+            data: peaks.data,
+        })
+        .into_response()),
+        // audiowaveform's .dat layout: version 1, 8-bit flag, then raw pairs.
+        Some("bin") | Some("dat") => {
+            let mut body = Vec::with_capacity(20 + peaks.data.len());
+            body.extend_from_slice(&1i32.to_le_bytes());
+            body.extend_from_slice(&1u32.to_le_bytes());
+            body.extend_from_slice(&(peaks.sample_rate as i32).to_le_bytes());
+            body.extend_from_slice(&(peaks.samples_per_point as i32).to_le_bytes());
+            body.extend_from_slice(&(peaks.points() as u32).to_le_bytes());
+            body.extend(peaks.data.iter().map(|v| *v as u8));
+            Ok(([(header::CONTENT_TYPE, "application/octet-stream")], body).into_response())
+        }
+        Some(other) => Err((StatusCode::BAD_REQUEST, format!("Unsupported format: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::media::storage::postgres::PostgresStorage;
+    use axum::body::to_bytes;
+    use uuid::Uuid;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=9-0", 100), None);
+        assert_eq!(parse_range("items=0-9", 100), None);
+        assert_eq!(parse_range("bytes=0-9", 0), None);
+    }
+
+    #[tokio::test]
+    async fn serves_only_the_requested_bytes() {
+        let pool = test_pool().await;
+        let id = Uuid::new_v4().to_string();
+        let hash = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+
+        let mut tx = pool.begin().await.unwrap();
+        blobs::store(&pool, &mut tx, &PostgresStorage::new(pool.clone()), &hash, b"0123456789", Some("audio/mpeg"))
+            .await
+            .unwrap();
+        sqlx::query!("INSERT INTO public.audio (id, url, content_hash) VALUES ($1, $1, $2)", id, hash)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let get = |range: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(range) = range {
+                headers.insert(header::RANGE, range.parse().unwrap());
+            }
+            selector(Extension(pool.clone()), Path(id.clone()), headers)
+        };
+
+        let resp = get(Some("bytes=3-5")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], "bytes 3-5/10");
+        assert_eq!(&to_bytes(resp.into_body(), usize::MAX).await.unwrap()[..], b"345");
+
+        let resp = get(Some("bytes=10-")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        let resp = get(None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(&to_bytes(resp.into_body(), usize::MAX).await.unwrap()[..], b"0123456789");
+
+        sqlx::query!("DELETE FROM public.audio WHERE id = $1", id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        if let Some(backend) = blobs::release(&mut conn, &hash).await.unwrap() {
+            blobs::purge(&pool, &hash, &backend).await.unwrap();
+        }
+    }
+}
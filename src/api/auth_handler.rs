@@ -1,33 +1,20 @@
 use axum::{Extension, Json};
-use axum::http::StatusCode;
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 use chrono::Utc;
-use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation, TokenData};
-use std::time::{Duration, Instant};
-use once_cell::sync::OnceCell;
-use tokio::sync::RwLock;
-use reqwest;
+use crate::authz::{Role, StaffPolicy};
+use crate::oidc::{IdClaims, Providers};
+use crate::session::{self, Device};
 
 #[derive(Debug, Deserialize)]
 pub struct TokenPayload {
     credential: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct GoogleClaims {
-    pub sub: String,
-    pub email: String,
-    pub name: String,
-    pub picture: String,
-    pub exp: usize,
-    pub iss: String,
-    pub aud: String,
-    #[serde(rename = "iat")]
-    pub issued_at: i64,
-}
-
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     sub: Option<String>,
@@ -38,152 +25,269 @@ pub struct AuthResponse {
     last_sign_in_at: i64,
 }
 
-#[derive(Debug, Deserialize)]
-struct GooglePublicKeys {
-    keys: Vec<GoogleKey>,
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    profile: AuthResponse,
+    user_id: String,
+    access_token: String,
+    token_type: &'static str,
+    expires_at: i64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct GoogleKey {
-    kid: String,
-    n: String,
-    e: String,
-}
+/// Records a new identity for `label`/`claims.sub`, attached to `user_id` if given.
+pub async fn insert_identity(
+    conn: &mut PgConnection,
+    label: &str,
+    claims: &IdClaims,
+    user_id: Option<&str>,
+) -> Result<Uuid, sqlx::Error> {
+    let new_id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.auth (id, provider, sub, email, email_verified, hosted_domain, fullname, picture, user_id, created_at, updated_at, last_sign_in_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $10, $10)
+        "#,
+        new_id,
+        label,
+        claims.sub,
+        claims.email,
+        claims.email_verified,
+        claims.hd,
+        claims.name,
+        claims.picture,
+        user_id,
+        Utc::now().timestamp(),
+    )
+    .execute(conn)
+    .await?;
 
-struct CertCache {
-    keys: Vec<GoogleKey>,
-    expires_at: Instant,
+    Ok(new_id)
 }
 
-static GOOGLE_CERT_CACHE: OnceCell<RwLock<Option<CertCache>>> = OnceCell::new();
+async fn create_user(conn: &mut PgConnection, auth_id: Uuid, claims: &IdClaims) -> Result<String, sqlx::Error> {
+    // Only a verified address is good enough to become the username.
+    let username = claims.email.as_ref().filter(|_| claims.email_verified);
 
-async fn get_google_key(kid: &str) -> Result<GoogleKey, String> {
-    let cache = GOOGLE_CERT_CACHE.get_or_init(|| RwLock::new(None));
-    {
-        let guard = cache.read().await;
-        if let Some(ref certs) = *guard {
-            if certs.expires_at > Instant::now() {
-                if let Some(key) = certs.keys.iter().find(|k| k.kid == kid).cloned() {
-                    return Ok(key);
-                }
-            }
-        }
-    }
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO public.users (auth_id, picture, username, email, fullname, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        RETURNING id
+        "#,
+        auth_id,
+        claims.picture,
+        username,
+        claims.email,
+        claims.name,
+        Utc::now().timestamp(),
+    )
+    .fetch_one(&mut *conn)
+    .await?;
 
-    let res = reqwest::get("https://www.googleapis.com/oauth2/v3/certs")
-        .await
-        .map_err(|e| format!("Failed to fetch certs: {}", e))?;
+    sqlx::query!(
+        r#"
+        UPDATE public.auth SET user_id = $2
+        WHERE id = $1
+        "#,
+        auth_id,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await?;
 
-    let keys: GooglePublicKeys = res.json().await.map_err(|e| format!("Invalid certs JSON: {}", e))?;
+    Ok(user_id)
+}
 
-    let expires = Instant::now() + Duration::from_secs(300);
-    {
-        let mut guard = cache.write().await;
-        *guard = Some(CertCache {
-            keys: keys.keys.clone(),
-            expires_at: expires,
-        });
-    }
+/// The one user that already holds `email` verified by another provider, if any.
+async fn verified_owner(conn: &mut PgConnection, email: &str) -> Result<Option<String>, sqlx::Error> {
+    let owners = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT user_id AS "user_id!" FROM public.auth
+        WHERE lower(email) = lower($1) AND email_verified AND user_id IS NOT NULL
+        "#,
+        email
+    )
+    .fetch_all(conn)
+    .await?;
 
-    keys.keys.into_iter().find(|k| k.kid == kid).ok_or("Key not found".into())
+    Ok(match owners.as_slice() {
+        [owner] => Some(owner.clone()),
+        _ => None,
+    })
 }
 
-async fn verify_google_token(token: &str, expected_aud: &str) -> Result<GoogleClaims, String> {
-    let header = decode_header(token).map_err(|e| e.to_string())?;
-    let kid = header.kid.ok_or("Missing 'kid' in JWT header")?;
+/// Finds or creates the identity and user for a verified sign-in. A new
+/// identity joins an existing user only when both providers vouch for the
+/// same email address; otherwise it starts a new user.
+async fn resolve_user(conn: &mut PgConnection, label: &str, claims: &IdClaims) -> Result<(Uuid, String), sqlx::Error> {
+    let now = Utc::now().timestamp();
 
-    let key = get_google_key(&kid).await?;
-    let decoding_key = DecodingKey::from_rsa_components(&key.n, &key.e)
-        .map_err(|e| format!("Invalid public key: {}", e))?;
+    let existing = sqlx::query!(
+        r#"
+        SELECT id, user_id FROM public.auth
+        WHERE provider = $1 AND sub = $2
+        FOR UPDATE
+        "#,
+        label,
+        claims.sub,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
 
-    let mut validation = Validation::new(Algorithm::RS256);
-    validation.set_audience(&[expected_aud]);
-    validation.set_issuer(&["https://accounts.google.com", "accounts.google.com"]);
+    if let Some(existing) = existing {
+        sqlx::query!(
+            r#"
+            UPDATE public.auth
+            SET email = $2, email_verified = $3, hosted_domain = $4, last_sign_in_at = $5, updated_at = $5
+            WHERE id = $1
+            "#,
+            existing.id,
+            claims.email,
+            claims.email_verified,
+            claims.hd,
+            now,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        // Identities from before linking may only be reachable through users.auth_id.
+        let user_id = match existing.user_id {
+            Some(user_id) => user_id,
+            None => {
+                let primary = sqlx::query_scalar!(
+                    r#"
+                    SELECT id FROM public.users
+                    WHERE auth_id = $1
+                    "#,
+                    existing.id
+                )
+                .fetch_optional(&mut *conn)
+                .await?;
 
-    let token_data: TokenData<GoogleClaims> =
-        decode(token, &decoding_key, &validation).map_err(|e| format!("Token decode error: {}", e))?;
+                match primary {
+                    Some(user_id) => {
+                        sqlx::query!(
+                            r#"
+                            UPDATE public.auth SET user_id = $2
+                            WHERE id = $1
+                            "#,
+                            existing.id,
+                            user_id,
+                        )
+                        .execute(&mut *conn)
+                        .await?;
+                        user_id
+                    }
+                    None => create_user(conn, existing.id, claims).await?,
+                }
+            }
+        };
+        return Ok((existing.id, user_id));
+    }
+
+    let owner = match claims.email.as_deref() {
+        Some(email) if claims.email_verified => verified_owner(conn, email).await?,
+        _ => None,
+    };
 
-    Ok(token_data.claims)
+    let auth_id = insert_identity(conn, label, claims, owner.as_deref()).await?;
+    let user_id = match owner {
+        Some(user_id) => {
+            tracing::info!("Linked new {} identity to user {} by verified email", label, user_id);
+            user_id
+        }
+        None => create_user(conn, auth_id, claims).await?,
+    };
+    Ok((auth_id, user_id))
 }
 
+/// Signs in with a Google ID token.
 pub async fn handler(
     Extension(pool): Extension<PgPool>,
+    Extension(providers): Extension<Providers>,
+    device: Device,
     Json(payload): Json<TokenPayload>,
-) -> Result<Json<AuthResponse>, (StatusCode, String)> {
-    let client_id = std::env::var("GOOGLE_CLIENT_ID")
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Missing GOOGLE_CLIENT_ID".into()))?;
+) -> Result<Response, (StatusCode, String)> {
+    sign_in(&pool, &providers, "google", &device, &payload.credential).await
+}
 
-    let claims = verify_google_token(&payload.credential, &client_id)
+/// Signs in with an ID token from any configured OpenID Connect provider.
+pub async fn provider(
+    Extension(pool): Extension<PgPool>,
+    Extension(providers): Extension<Providers>,
+    Path(name): Path<String>,
+    device: Device,
+    Json(payload): Json<TokenPayload>,
+) -> Result<Response, (StatusCode, String)> {
+    sign_in(&pool, &providers, &name, &device, &payload.credential).await
+}
+
+async fn sign_in(
+    pool: &PgPool,
+    providers: &Providers,
+    name: &str,
+    device: &Device,
+    credential: &str,
+) -> Result<Response, (StatusCode, String)> {
+    let provider = providers
+        .get(name)
+        .ok_or((StatusCode::NOT_FOUND, format!("Unknown provider: {}", name)))?;
+
+    let claims = providers
+        .verify(provider, credential)
         .await
         .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Token verification failed: {}", e)))?;
 
-    let now = Utc::now().timestamp();
+    complete_sign_in(pool, &provider.label, &claims, device).await
+}
+
+/// Signs in the user behind an already verified identity and opens a session for them.
+pub async fn complete_sign_in(
+    pool: &PgPool,
+    label: &str,
+    claims: &IdClaims,
+    device: &Device,
+) -> Result<Response, (StatusCode, String)> {
+    // Concurrent first sign-ins of one identity race on the (provider, sub)
+    // index; the loser starts over and finds the winner's rows.
+    let mut retried = false;
+    let (mut tx, auth_id, user_id) = loop {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        match resolve_user(&mut tx, label, claims).await {
+            Ok((auth_id, user_id)) => break (tx, auth_id, user_id),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() && !retried => retried = true,
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Sign-in failed: {}", e))),
+        }
+    };
 
-    let auth = sqlx::query!(
+    let role = sqlx::query_scalar!(
         r#"
-        SELECT id FROM public.auth
-        WHERE provider = $1 AND sub = $2
+        SELECT role FROM public.users
+        WHERE id = $1
         "#,
-        "Google",
-        claims.sub,
+        user_id
     )
-    .fetch_optional(&pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let auth_id = if let Some(existing) = auth {
-        sqlx::query!(
-            r#"
-            UPDATE public.auth
-            SET last_sign_in_at = $1
-            WHERE id = $2
-            "#,
-            now,
-            existing.id
-        )
-        .execute(&pool)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-        existing.id
-    } else {
-        let new_id = Uuid::new_v4();
-
-        sqlx::query!(
-            r#"
-            INSERT INTO public.auth (id, provider, sub, email, fullname, picture, created_at, updated_at, last_sign_in_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $7)
-            "#,
-            new_id,
-            "Google",
-            claims.sub,
-            claims.email,
-            claims.name,
-            claims.picture,
-            now,
-        )
-        .execute(&pool)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to insert auth: {}", e)))?;
+    // Staff must come in through an allowed domain or address; nothing is saved otherwise.
+    let staff = StaffPolicy::from_env();
+    if Role::parse(&role).is_some_and(|role| staff.restricts(role))
+        && !staff.allows(label, claims.hd.as_deref(), claims.email.as_deref(), claims.email_verified)
+    {
+        return Err((StatusCode::FORBIDDEN, "Staff accounts must sign in from an allowed domain or address".into()));
+    }
 
-        sqlx::query!(
-            r#"
-            INSERT INTO public.users (auth_id, picture, username, email, fullname, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $6)
-            "#,
-            new_id,
-            claims.picture,
-            claims.email,
-            claims.email,
-            claims.name,
-            now,
-        )
-        .execute(&pool)
+    tx.commit()
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to insert user: {}", e)))?;
-
-        new_id
-    };
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
     let row = sqlx::query_as!(
         AuthResponse,
@@ -200,9 +304,66 @@ pub async fn handler(
         "#,
         auth_id
     )
-    .fetch_one(&pool)
+    .fetch_one(pool)
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch after insert failed: {}", e)))?;
 
-    Ok(Json(row))
+    let (access, cookie) = session::create(pool, &user_id, device)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(SessionResponse {
+            profile: row,
+            user_id,
+            access_token: access.token,
+            token_type: "Bearer",
+            expires_at: access.expires_at,
+        }),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    fn claims(email: &str, verified: bool) -> IdClaims {
+        IdClaims {
+            sub: Uuid::new_v4().to_string(),
+            email: Some(email.to_string()),
+            email_verified: verified,
+            name: None,
+            picture: None,
+            hd: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn links_by_email_only_when_both_sides_verified_it() {
+        let pool = test_pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let (email, other) = (format!("{}@example.com", Uuid::new_v4()), format!("{}@example.com", Uuid::new_v4()));
+        let mut sign_in = async |label: &str, claims: IdClaims| resolve_user(&mut conn, label, &claims).await.unwrap().1;
+
+        let owner = sign_in("Google", claims(&email, true)).await;
+        assert_eq!(sign_in("Microsoft", claims(&email.to_uppercase(), true)).await, owner);
+        let unverified = sign_in("Apple", claims(&email, false)).await;
+        assert_ne!(unverified, owner);
+
+        // An address the existing identity never verified does not vouch for anyone.
+        let first = sign_in("Google", claims(&other, false)).await;
+        let second = sign_in("Microsoft", claims(&other, true)).await;
+        assert_ne!(second, first);
+
+        assert_eq!(verified_owner(&mut conn, &email).await.unwrap(), Some(owner.clone()));
+        assert_eq!(verified_owner(&mut conn, &other).await.unwrap(), Some(second.clone()));
+
+        sqlx::query!("DELETE FROM public.users WHERE id = ANY($1)", &[owner, unverified, first, second][..])
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
 }
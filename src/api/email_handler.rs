@@ -0,0 +1,76 @@
+use axum::{Extension, Json};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::api::auth_handler::complete_sign_in;
+use crate::magic_link::{self, RequestError, PROVIDER};
+use crate::oidc::IdClaims;
+use crate::session::Device;
+
+#[derive(Debug, Deserialize)]
+pub struct EmailData {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyData {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkSent {
+    pub status: &'static str,
+    /// Seconds the link stays valid.
+    pub expires_in: i64,
+}
+
+/// Emails a sign-in link. Known and unknown addresses get the same answer.
+pub async fn request(
+    Extension(pool): Extension<PgPool>,
+    device: Device,
+    Json(payload): Json<EmailData>,
+) -> Result<Response, (StatusCode, String)> {
+    match magic_link::request(&pool, &payload.email, device.ip.as_deref()).await {
+        Ok(()) => Ok((
+            StatusCode::ACCEPTED,
+            Json(LinkSent { status: "sent", expires_in: magic_link::ttl() }),
+        )
+            .into_response()),
+        Err(RequestError::InvalidEmail) => Err((StatusCode::UNPROCESSABLE_ENTITY, "Invalid email address".into())),
+        Err(RequestError::Throttled) => Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, "3600")],
+            "Too many sign-in links for this address; try again later",
+        )
+            .into_response()),
+        Err(RequestError::Failed(e)) => {
+            tracing::warn!("Sign-in link not sent: {}", e);
+            Err((StatusCode::BAD_GATEWAY, "Could not send the sign-in email".into()))
+        }
+    }
+}
+
+/// Trades the token from a sign-in link for a session.
+pub async fn verify(
+    Extension(pool): Extension<PgPool>,
+    device: Device,
+    Json(payload): Json<VerifyData>,
+) -> Result<Response, (StatusCode, String)> {
+    let email = magic_link::consume(&pool, &payload.token)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or((StatusCode::UNAUTHORIZED, "Sign-in link is invalid, expired or already used".to_string()))?;
+
+    // Opening the link proves the address, so it counts as verified for linking and staff checks.
+    let claims = IdClaims {
+        sub: email.clone(),
+        email: Some(email),
+        email_verified: true,
+        name: None,
+        picture: None,
+        hd: None,
+    };
+
+    complete_sign_in(&pool, PROVIDER, &claims, &device).await
+}
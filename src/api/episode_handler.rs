@@ -0,0 +1,89 @@
+use axum::{Extension, Json};
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::api::podcast_feed::strip_illegal;
+
+#[derive(Debug, Deserialize)]
+pub struct EpisodeData {
+    pub audio_id: String,
+    pub title: String,
+    pub description: String,
+    pub season: Option<i32>,
+    pub episode: Option<i32>,
+    pub episode_type: Option<String>,
+    pub explicit: Option<bool>,
+    pub artwork_url: Option<String>,
+    /// Unix timestamp; defaults to now. Episodes dated in the future stay out of the feed until then.
+    pub published_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EpisodeResponse {
+    pub id: String,
+    pub audio_id: String,
+    pub title: String,
+    pub description: String,
+    pub season: Option<i32>,
+    pub episode: Option<i32>,
+    pub episode_type: String,
+    pub explicit: bool,
+    pub artwork_url: Option<String>,
+    pub published_at: i64,
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    Json(payload): Json<EpisodeData>,
+) -> Result<Json<EpisodeResponse>, (StatusCode, String)> {
+    let episode_type = payload.episode_type.unwrap_or_else(|| "full".to_string());
+    if !matches!(episode_type.as_str(), "full" | "trailer" | "bonus") {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid episode_type: {}", episode_type)));
+    }
+
+    let audio = sqlx::query_scalar!(
+        r#"
+        SELECT content_hash FROM public.audio
+        WHERE id = $1
+        "#,
+        payload.audio_id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Audio lookup failed: {}", e)))?;
+
+    match audio {
+        None => return Err((StatusCode::UNPROCESSABLE_ENTITY, "Unknown audio_id".into())),
+        Some(None) => return Err((StatusCode::UNPROCESSABLE_ENTITY, "Audio has not been ingested yet".into())),
+        Some(Some(_)) => {}
+    }
+
+    let now = Utc::now().timestamp();
+
+    let row = sqlx::query_as!(
+        EpisodeResponse,
+        r#"
+        INSERT INTO public.episodes (id, audio_id, title, description, season, episode, episode_type, explicit, artwork_url, published_at, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11)
+        RETURNING id, audio_id, title, description, season, episode, episode_type, explicit, artwork_url, published_at
+        "#,
+        Uuid::new_v4().to_string(),
+        payload.audio_id,
+        strip_illegal(&payload.title),
+        strip_illegal(&payload.description),
+        payload.season,
+        payload.episode,
+        episode_type,
+        payload.explicit.unwrap_or(false),
+        payload.artwork_url,
+        payload.published_at.unwrap_or(now),
+        now,
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Insert failed: {}", e)))?;
+
+    Ok(Json(row))
+}
@@ -1,8 +1,7 @@
 use axum::{Extension, Json};
 use axum::http::StatusCode;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use sqlx::PgPool;
-use uuid::Uuid;
 
 #[derive(Debug, Serialize)]
 pub struct GlossaryResponse {
@@ -0,0 +1,260 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::api::auth_handler::insert_identity;
+use crate::api::identity_selector::{identities, IdentityResponse};
+use crate::oidc::{IdClaims, Providers};
+use crate::session::AuthUser;
+
+#[derive(Debug, Deserialize)]
+pub struct LinkData {
+    pub provider: String,
+    pub credential: String,
+}
+
+/// Links another provider's identity to the signed-in user. The ID token proves
+/// the caller controls that identity, so no email match is needed.
+pub async fn link(
+    Extension(pool): Extension<PgPool>,
+    Extension(providers): Extension<Providers>,
+    user: AuthUser,
+    Json(payload): Json<LinkData>,
+) -> Result<Json<Vec<IdentityResponse>>, (StatusCode, String)> {
+    let provider = providers
+        .get(&payload.provider)
+        .ok_or((StatusCode::NOT_FOUND, format!("Unknown provider: {}", payload.provider)))?;
+
+    let claims = providers
+        .verify(provider, &payload.credential)
+        .await
+        .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Token verification failed: {}", e)))?;
+
+    attach(&pool, &provider.label, &claims, &user.user_id).await?;
+
+    Ok(Json(identities(&pool, &user.user_id).await?))
+}
+
+/// Attaches the identity behind verified `claims` to `user_id`, recording it if
+/// it is new. Fails with 409 if it belongs to another user.
+async fn attach(pool: &PgPool, label: &str, claims: &IdClaims, user_id: &str) -> Result<(), (StatusCode, String)> {
+    let db = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Link failed: {}", e));
+    let mut tx = pool.begin().await.map_err(db)?;
+
+    let existing = sqlx::query!(
+        r#"
+        SELECT a.id, coalesce(a.user_id, u.id) AS owner
+        FROM public.auth a
+        LEFT JOIN public.users u ON u.auth_id = a.id
+        WHERE a.provider = $1 AND a.sub = $2
+        FOR UPDATE OF a
+        "#,
+        label,
+        claims.sub,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    match existing {
+        Some(row) if row.owner.as_deref().is_some_and(|owner| owner != user_id) => {
+            return Err((StatusCode::CONFLICT, "This sign-in is already linked to another account".into()));
+        }
+        // Either already ours or an identity that never got a user; claim it.
+        Some(row) => {
+            sqlx::query!(
+                r#"
+                UPDATE public.auth SET user_id = $2, email = $3, email_verified = $4, hosted_domain = $5, updated_at = $6
+                WHERE id = $1
+                "#,
+                row.id,
+                user_id,
+                claims.email,
+                claims.email_verified,
+                claims.hd,
+                Utc::now().timestamp(),
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(db)?;
+        }
+        None => {
+            insert_identity(&mut tx, label, claims, Some(user_id))
+                .await
+                .map_err(db)?;
+        }
+    }
+
+    tx.commit().await.map_err(db)?;
+
+    Ok(())
+}
+
+/// Removes one of the signed-in user's identities. The last one cannot be
+/// removed, and removing the primary one promotes the oldest remaining.
+pub async fn unlink(
+    Extension(pool): Extension<PgPool>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<IdentityResponse>>, (StatusCode, String)> {
+    let id = Uuid::parse_str(&id).map_err(|_| (StatusCode::NOT_FOUND, "Identity not found".to_string()))?;
+
+    let db = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Unlink failed: {}", e));
+    let mut tx = pool.begin().await.map_err(db)?;
+
+    // Lock the user so concurrent unlinks cannot remove both remaining identities.
+    let linked = sqlx::query_scalar!(
+        r#"
+        SELECT a.id FROM public.users u
+        JOIN public.auth a ON a.user_id = u.id
+        WHERE u.id = $1
+        ORDER BY a.created_at
+        FOR UPDATE OF u
+        "#,
+        user.user_id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    if !linked.contains(&id) {
+        return Err((StatusCode::NOT_FOUND, "Identity not found".into()));
+    }
+    let Some(&successor) = linked.iter().find(|other| **other != id) else {
+        return Err((StatusCode::CONFLICT, "Cannot unlink the only sign-in method".into()));
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE public.users SET auth_id = $2
+        WHERE id = $1 AND auth_id = $3
+        "#,
+        user.user_id,
+        successor,
+        id,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM public.auth WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    tx.commit().await.map_err(db)?;
+
+    Ok(Json(identities(&pool, &user.user_id).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authz::Role;
+    use crate::db::test_pool;
+    use crate::session::Credential;
+
+    fn claims(sub: &str) -> IdClaims {
+        IdClaims {
+            sub: sub.to_string(),
+            email: Some(format!("{}@example.com", sub)),
+            email_verified: true,
+            name: None,
+            picture: None,
+            hd: None,
+        }
+    }
+
+    fn caller(user_id: &str) -> AuthUser {
+        AuthUser { user_id: user_id.to_string(), role: Role::Reader, credential: Credential::Session("session".into()) }
+    }
+
+    /// A user whose primary identity is a fresh `Test` sign-in, with that identity's subject.
+    async fn user(pool: &PgPool) -> (String, Uuid, String) {
+        let sub = Uuid::new_v4().to_string();
+        let mut conn = pool.acquire().await.unwrap();
+        let auth_id = insert_identity(&mut conn, "Test", &claims(&sub), None).await.unwrap();
+        let user_id = sqlx::query_scalar!("INSERT INTO public.users (auth_id) VALUES ($1) RETURNING id", auth_id)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE public.auth SET user_id = $2 WHERE id = $1", auth_id, user_id)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        (user_id, auth_id, sub)
+    }
+
+    async fn primary(pool: &PgPool, user_id: &str) -> Option<Uuid> {
+        sqlx::query_scalar!("SELECT auth_id FROM public.users WHERE id = $1", user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn remove(pool: &PgPool, users: &[String]) {
+        sqlx::query!("DELETE FROM public.users WHERE id = ANY($1)", users)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_to_link_an_identity_another_user_owns() {
+        let pool = test_pool().await;
+        let (mine, _, my_sub) = user(&pool).await;
+        let (theirs, their_auth, their_sub) = user(&pool).await;
+
+        let (status, _) = attach(&pool, "Test", &claims(&their_sub), &mine).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let owner = sqlx::query_scalar!("SELECT user_id FROM public.auth WHERE id = $1", their_auth)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(owner.as_deref(), Some(theirs.as_str()));
+
+        // Linking one's own identity again is harmless, and a new one is added.
+        attach(&pool, "Test", &claims(&my_sub), &mine).await.unwrap();
+        attach(&pool, "Other", &claims(&Uuid::new_v4().to_string()), &mine).await.unwrap();
+        let mut linked = identities(&pool, &mine).await.unwrap().into_iter().map(|i| i.provider).collect::<Vec<_>>();
+        linked.sort();
+        assert_eq!(linked, ["Other", "Test"]);
+
+        remove(&pool, &[mine, theirs]).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_identity_and_promotes_the_next_primary() {
+        let pool = test_pool().await;
+        let (mine, first, _) = user(&pool).await;
+        let (theirs, their_auth, _) = user(&pool).await;
+        let remove_identity = |id: Uuid| unlink(Extension(pool.clone()), caller(&mine), Path(id.to_string()));
+
+        let (status, _) = remove_identity(first).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = remove_identity(their_auth).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        attach(&pool, "Other", &claims(&Uuid::new_v4().to_string()), &mine).await.unwrap();
+        let second = identities(&pool, &mine).await.unwrap().into_iter().find(|i| i.provider == "Other").unwrap();
+        let second = Uuid::parse_str(&second.id).unwrap();
+
+        let Json(left) = remove_identity(first).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert!(left[0].primary);
+        assert_eq!(primary(&pool, &mine).await, Some(second));
+
+        let (status, _) = remove_identity(second).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        remove(&pool, &[mine, theirs]).await;
+    }
+}
@@ -0,0 +1,43 @@
+use axum::{Extension, Json};
+use axum::http::StatusCode;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::session::AuthUser;
+
+#[derive(Debug, Serialize)]
+pub struct IdentityResponse {
+    pub id: String,
+    pub provider: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub created_at: i64,
+    pub last_sign_in_at: i64,
+    /// Whether this is the identity the account was created with.
+    pub primary: bool,
+}
+
+pub async fn identities(pool: &PgPool, user_id: &str) -> Result<Vec<IdentityResponse>, (StatusCode, String)> {
+    sqlx::query_as!(
+        IdentityResponse,
+        r#"
+        SELECT a.id::text AS "id!", a.provider, a.email, a.email_verified, a.created_at, a.last_sign_in_at,
+               u.auth_id IS NOT DISTINCT FROM a.id AS "primary!"
+        FROM public.auth a
+        JOIN public.users u ON u.id = a.user_id
+        WHERE a.user_id = $1
+        ORDER BY a.created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))
+}
+
+/// The sign-in identities linked to the signed-in user.
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    user: AuthUser,
+) -> Result<Json<Vec<IdentityResponse>>, (StatusCode, String)> {
+    Ok(Json(identities(&pool, &user.user_id).await?))
+}
@@ -71,9 +71,18 @@
 // }
 use axum::{Extension, Json};
 use axum::http::StatusCode;
+use axum::response::Response;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use reqwest::Client;
+use uuid::Uuid;
+use crate::api::job_selector::accept;
+use crate::jobs;
+use crate::media::blobs;
+use crate::media::fetch::{content_hash, fetch, MediaKind};
+use crate::media::placeholder::placeholder;
+use crate::media::sanitize::sanitize;
+use crate::media::storage::SharedStorage;
 
 #[derive(Debug, Deserialize)]
 pub struct ImageData {
@@ -85,43 +94,136 @@ pub struct ImageResponse {
     pub id: Option<String>,
     pub url: Option<String>,
     pub is_indb: Option<i32>,
+    pub content_hash: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub format: Option<String>,
+    pub blurhash: Option<String>,
+    pub lqip: Option<String>,
+    pub created: bool,
+    pub changed: bool,
 }
 
+/// Queues ingestion of `url`; the job runs [`ingest`] in the background.
 pub async fn handler(
     Extension(pool): Extension<PgPool>,
     Json(payload): Json<ImageData>,
-) -> Result<Json<ImageResponse>, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
+    accept(&pool, jobs::INGEST_IMAGE, &payload.url).await
+}
 
-    let client = Client::new();
-    let resp = client
-        .get(&payload.url)
-        .send()
-        .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Download failed: {}", e)))?;
+/// Downloads and stores `url`, replacing whatever was stored for it before.
+pub async fn ingest(pool: &PgPool, storage: &SharedStorage, url: &str) -> Result<ImageResponse, (StatusCode, String)> {
+    let fetched = fetch(url, MediaKind::Image).await?;
 
-    if !resp.status().is_success() {
-        return Err((StatusCode::BAD_REQUEST, format!("Failed to fetch image: HTTP {}", resp.status())));
-    }
+    let (image, preview) = tokio::task::spawn_blocking(move || {
+        let image = sanitize(fetched.bytes.to_vec())?;
+        let preview = placeholder(&image.data)?;
+        Ok::<_, String>((image, preview))
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Image processing task failed: {}", e)))?
+    .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let hash = content_hash(&image.data);
+
+    // Garbage collection can remove the blob between `store` finding it and the
+    // upsert referencing it, which then fails on the foreign key; the next attempt
+    // stores it again.
+    let mut attempt = 1;
+    let (row, released) = loop {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Transaction failed: {}", e)))?;
+
+        blobs::store(pool, &mut tx, storage.as_ref(), &hash, &image.data, Some(image.mime))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+        let now = Utc::now().timestamp();
 
-    let bytes = resp
-        .bytes()
+        // `created` comes from the upsert itself, so concurrent first ingests of a
+        // URL cannot both report it as new.
+        let row = match sqlx::query!(
+            r#"
+            WITH previous AS (
+                SELECT content_hash FROM public.images
+                WHERE url = $2
+            )
+            INSERT INTO public.images (id, url, is_indb, content_hash, fetched_at, width, height, format, blurhash, lqip)
+            VALUES ($1, $2, 1, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (url) DO UPDATE
+            SET data = NULL,
+                is_indb = 1,
+                content_hash = EXCLUDED.content_hash,
+                fetched_at = EXCLUDED.fetched_at,
+                width = EXCLUDED.width,
+                height = EXCLUDED.height,
+                format = EXCLUDED.format,
+                blurhash = EXCLUDED.blurhash,
+                lqip = EXCLUDED.lqip
+            RETURNING id, url, is_indb, content_hash, width, height, format, blurhash, lqip,
+                (xmax = 0) AS "created!",
+                (SELECT content_hash FROM previous) AS previous_hash
+            "#,
+            Uuid::new_v4().to_string(),
+            url,
+            hash,
+            now,
+            image.width as i32,
+            image.height as i32,
+            image.format,
+            preview.blurhash,
+            preview.lqip,
+        )
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Read bytes failed: {}", e)))?;
-
-    let updated = sqlx::query_as!(
-        ImageResponse,
-        r#"
-        UPDATE public.images
-        SET data = $1, is_indb = 1
-        WHERE url = $2
-        RETURNING id, url, is_indb
-        "#,
-        bytes.as_ref(),
-        payload.url
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Update failed: {}", e)))?;
+        {
+            Ok(row) => row,
+            Err(e) if blobs::collected(&e) && attempt < blobs::STORE_ATTEMPTS => {
+                tracing::warn!("Blob {} was collected while ingesting {}, storing it again", hash, url);
+                attempt += 1;
+                continue;
+            }
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Upsert failed: {}", e))),
+        };
+
+        let mut released = None;
+        if let Some(old_hash) = row.previous_hash.as_deref()
+            && old_hash != hash
+        {
+            released = blobs::release(&mut tx, old_hash)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Commit failed: {}", e)))?;
+
+        break (row, released);
+    };
+
+    let created = row.created;
+    let previous = row.previous_hash;
+
+    if let (Some(old_hash), Some(backend)) = (previous.as_deref(), released.as_deref())
+        && let Err(e) = blobs::purge(pool, old_hash, backend).await
+    {
+        tracing::warn!("Failed to purge released blob {}: {}", old_hash, e);
+    }
 
-    Ok(Json(updated))
+    Ok(ImageResponse {
+        id: Some(row.id),
+        url: row.url,
+        is_indb: row.is_indb,
+        content_hash: row.content_hash,
+        width: row.width,
+        height: row.height,
+        format: row.format,
+        blurhash: row.blurhash,
+        lqip: row.lqip,
+        created,
+        changed: previous.as_deref() != Some(hash.as_str()),
+    })
 }
@@ -0,0 +1,345 @@
+use axum::{Extension, Json};
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::media::blobs;
+use crate::media::fetch::content_hash;
+use crate::media::storage::SharedStorage;
+use crate::media::variants::{VariantQuery, VariantSpec};
+
+#[derive(Debug, Serialize)]
+pub struct ImageMeta {
+    pub id: String,
+    pub url: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub format: Option<String>,
+    pub blurhash: Option<String>,
+    pub lqip: Option<String>,
+}
+
+/// Absolute URLs in `content`, whether in HTML attributes, Markdown links or bare text.
+fn content_urls(content: &str) -> Vec<String> {
+    let is_boundary = |c: char| c.is_whitespace() || "\"'<>()[]`".contains(c);
+    let mut urls = std::collections::BTreeSet::new();
+
+    for (start, _) in content.match_indices("http") {
+        let rest = &content[start..];
+        if !(rest.starts_with("http://") || rest.starts_with("https://")) {
+            continue;
+        }
+        let url = &rest[..rest.find(is_boundary).unwrap_or(rest.len())];
+        // Bare URLs in prose may run into sentence punctuation.
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        for url in [url, trimmed] {
+            urls.insert(url.to_string());
+            // HTML escapes `&` in attribute values.
+            if url.contains("&amp;") {
+                urls.insert(url.replace("&amp;", "&"));
+            }
+        }
+    }
+    urls.into_iter().collect()
+}
+
+/// Metadata for every ingested image whose URL appears in `content`.
+pub async fn embedded_images(pool: &PgPool, content: &str) -> Result<Vec<ImageMeta>, sqlx::Error> {
+    let urls = content_urls(content);
+    if urls.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_as!(
+        ImageMeta,
+        r#"
+        SELECT id, url, width, height, format, blurhash, lqip
+        FROM public.images
+        WHERE is_indb = 1
+          AND url = ANY($1)
+        "#,
+        &urls[..]
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn meta(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> Result<Json<ImageMeta>, (StatusCode, String)> {
+    let row = sqlx::query_as!(
+        ImageMeta,
+        r#"
+        SELECT id, url, width, height, format, blurhash, lqip
+        FROM public.images
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "Image not found".into()))?;
+
+    Ok(Json(row))
+}
+
+/// A bodiless `304` if the client's `If-None-Match` already names the blob `hash`.
+fn not_modified(headers: &HeaderMap, hash: &str) -> Option<Response> {
+    let etag = format!("\"{}\"", hash);
+    let matches = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+
+    matches.then(|| {
+        (
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::CACHE_CONTROL, "public, max-age=86400".to_string()),
+                (header::ETAG, etag),
+            ],
+        )
+            .into_response()
+    })
+}
+
+fn image_response(data: Vec<u8>, mime: Option<String>, hash: &str) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, mime.unwrap_or_else(|| "application/octet-stream".into())),
+            (header::CACHE_CONTROL, "public, max-age=86400".into()),
+            (header::ETAG, format!("\"{}\"", hash)),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<String>,
+    Query(query): Query<VariantQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let image = sqlx::query!(
+        r#"
+        SELECT i.content_hash AS "content_hash?", b.mime AS "mime?"
+        FROM public.images i
+        LEFT JOIN public.media_blobs b ON b.hash = i.content_hash
+        WHERE i.id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "Image not found".into()))?;
+
+    let source_hash = image
+        .content_hash
+        .ok_or((StatusCode::NOT_FOUND, "Image has not been ingested yet".into()))?;
+
+    let spec = VariantSpec::parse(&query).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let Some(spec) = spec else {
+        if let Some(unchanged) = not_modified(&headers, &source_hash) {
+            return Ok(unchanged);
+        }
+        let data = blobs::load(&pool, &source_hash)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+            .ok_or((StatusCode::NOT_FOUND, "Image payload is missing".into()))?;
+        return Ok(image_response(data, image.mime, &source_hash));
+    };
+
+    spec.authorize(&id, query.sig.as_deref())
+        .map_err(|e| (StatusCode::FORBIDDEN, e))?;
+
+    let variant_key = spec.key();
+
+    let cached = sqlx::query!(
+        r#"
+        SELECT v.content_hash, b.mime
+        FROM public.image_variants v
+        JOIN public.media_blobs b ON b.hash = v.content_hash
+        WHERE v.image_id = $1 AND v.variant = $2 AND v.source_hash = $3
+        "#,
+        id,
+        variant_key,
+        source_hash
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Variant lookup failed: {}", e)))?;
+
+    if let Some(cached) = &cached
+        && let Some(unchanged) = not_modified(&headers, &cached.content_hash)
+    {
+        return Ok(unchanged);
+    }
+
+    if let Some(cached) = cached
+        && let Some(data) = blobs::load(&pool, &cached.content_hash)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+    {
+        return Ok(image_response(data, cached.mime, &cached.content_hash));
+    }
+
+    let source = blobs::load(&pool, &source_hash)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or((StatusCode::NOT_FOUND, "Image payload is missing".into()))?;
+
+    let rendered = tokio::task::spawn_blocking(move || spec.render(&source))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Render task failed: {}", e)))?
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    let hash = content_hash(&rendered.data);
+    let mime = rendered.format.mime();
+
+    let mut conn = pool
+        .acquire()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Connection failed: {}", e)))?;
+
+    blobs::store(&pool, &mut conn, storage.as_ref(), &hash, &rendered.data, Some(mime))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.image_variants (image_id, variant, source_hash, content_hash, width, height, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (image_id, variant) DO UPDATE
+        SET source_hash = EXCLUDED.source_hash,
+            content_hash = EXCLUDED.content_hash,
+            width = EXCLUDED.width,
+            height = EXCLUDED.height,
+            created_at = EXCLUDED.created_at
+        "#,
+        id,
+        variant_key,
+        source_hash,
+        hash,
+        rendered.width as i32,
+        rendered.height as i32,
+        Utc::now().timestamp(),
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Variant insert failed: {}", e)))?;
+
+    Ok(image_response(rendered.data, Some(mime.to_string()), &hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::media::storage::postgres::PostgresStorage;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[test]
+    fn extracts_urls_from_html_and_markdown() {
+        let content = r#"<p><img src="https://cdn.example.com/a.jpg" alt="A"></p>
+            ![B](https://cdn.example.com/b.png "title") see http://example.com/c.gif.
+            <img srcset="https://cdn.example.com/d.webp 2x">"#;
+        assert_eq!(
+            content_urls(content),
+            vec![
+                "http://example.com/c.gif",
+                "http://example.com/c.gif.",
+                "https://cdn.example.com/a.jpg",
+                "https://cdn.example.com/b.png",
+                "https://cdn.example.com/d.webp",
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_whole_urls_only() {
+        // A stored image at a prefix of a URL in the content must not match.
+        assert_eq!(content_urls("<img src='https://x.test/a.jpg?w=2'>"), vec!["https://x.test/a.jpg?w=2"]);
+        assert!(content_urls("no links, just httpd and http:/ typos").is_empty());
+        assert!(content_urls("").is_empty());
+    }
+
+    #[test]
+    fn unescapes_html_ampersands() {
+        assert_eq!(
+            content_urls(r#"<img src="https://x.test/i?a=1&amp;b=2">"#),
+            vec!["https://x.test/i?a=1&amp;b=2", "https://x.test/i?a=1&b=2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_matching_etags_with_not_modified() {
+        let pool = test_pool().await;
+        let storage: SharedStorage = Arc::new(PostgresStorage::new(pool.clone()));
+        let id = Uuid::new_v4().to_string();
+        let seed = Uuid::new_v4().into_bytes();
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(200, 100, image::Rgb([seed[0], seed[1], seed[2]])))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let hash = content_hash(&png);
+
+        let mut tx = pool.begin().await.unwrap();
+        blobs::store(&pool, &mut tx, storage.as_ref(), &hash, &png, Some("image/png")).await.unwrap();
+        sqlx::query!("INSERT INTO public.images (id, url, is_indb, content_hash) VALUES ($1, $1, 1, $2)", id, hash)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let get = |w: Option<u32>, etag: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(etag) = etag {
+                headers.insert(header::IF_NONE_MATCH, etag.parse().unwrap());
+            }
+            let query = VariantQuery { w, h: None, fit: None, fmt: None, sig: None };
+            selector(Extension(pool.clone()), Extension(storage.clone()), Path(id.clone()), Query(query), headers)
+        };
+
+        let original = get(None, None).await.unwrap();
+        assert_eq!(original.status(), StatusCode::OK);
+        let etag = original.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(etag, format!("\"{}\"", hash));
+
+        assert_eq!(get(None, Some(&etag)).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(get(None, Some(&format!("\"other\", W/{}", etag))).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(get(None, Some("\"other\"")).await.unwrap().status(), StatusCode::OK);
+
+        let variant = get(Some(160), None).await.unwrap();
+        assert_eq!(variant.status(), StatusCode::OK);
+        let variant_etag = variant.headers()[header::ETAG].to_str().unwrap().to_string();
+        let unchanged = get(Some(160), Some(&variant_etag)).await.unwrap();
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(unchanged.headers()[header::ETAG], variant_etag.as_str());
+        assert_eq!(get(Some(160), Some(&etag)).await.unwrap().status(), StatusCode::OK);
+
+        sqlx::query!("DELETE FROM public.images WHERE id = $1", id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        for blob in [hash, variant_etag.trim_matches('"').to_string()] {
+            if let Some(backend) = blobs::release(&mut conn, &blob).await.unwrap() {
+                blobs::purge(&pool, &blob, &backend).await.unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use crate::jobs;
+use crate::media::fetch::validate_url;
+
+#[derive(Debug, Serialize)]
+pub struct JobAccepted {
+    pub job_id: String,
+    pub status: String,
+    pub status_url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobResponse {
+    pub id: String,
+    pub kind: String,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// When a queued job is next due, as a Unix timestamp.
+    pub run_at: i64,
+    pub last_error: Option<String>,
+    pub result: Option<Value>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// Queues an ingestion job for `url` and answers `202 Accepted` pointing at its status.
+/// Re-submitting a URL that is still pending returns the existing job.
+pub async fn accept(pool: &PgPool, kind: &str, url: &str) -> Result<Response, (StatusCode, String)> {
+    validate_url(url)?;
+
+    let job = jobs::enqueue(pool, kind, json!({ "url": url }), Some(&format!("{}:{}", kind, url)))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let status_url = format!("/jobs/{}", job.id);
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, status_url.clone())],
+        Json(JobAccepted { job_id: job.id, status: job.status, status_url }),
+    )
+        .into_response())
+}
+
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> Result<Json<JobResponse>, (StatusCode, String)> {
+    let job = sqlx::query_as!(
+        JobResponse,
+        r#"
+        SELECT id, kind, status, attempts, max_attempts, run_at, last_error, result, created_at, updated_at
+        FROM public.jobs
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "Job not found".into()))?;
+
+    Ok(Json(job))
+}
@@ -0,0 +1,30 @@
+use axum::{Extension, Json};
+use axum::http::StatusCode;
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::events::{EventBus, NowPlaying};
+use crate::stream;
+
+#[derive(Debug, Deserialize)]
+pub struct NowPlayingData {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub listeners: Option<i32>,
+}
+
+/// Lets playout software push the current track when there is no stream server to poll.
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Json(payload): Json<NowPlayingData>,
+) -> Result<Json<NowPlaying>, (StatusCode, String)> {
+    if payload.title.is_none() && payload.artist.is_none() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Send a title or an artist".into()));
+    }
+
+    let now_playing = stream::record_track(&pool, &bus, payload.title, payload.artist, payload.listeners)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Now playing update failed: {}", e)))?;
+
+    Ok(Json(now_playing))
+}
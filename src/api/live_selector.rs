@@ -0,0 +1,146 @@
+use std::convert::Infallible;
+use axum::Extension;
+use axum::extract::Query;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::{Json, Response};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use futures_util::{future, stream, Stream, StreamExt};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::broadcast::error::RecvError;
+use crate::events::{Event, EventBus, NowPlaying};
+
+#[derive(Debug, Deserialize)]
+pub struct TopicQuery {
+    /// Comma-separated topics, e.g. `now_playing,article_published`; all when omitted.
+    pub topics: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Topics(Option<Vec<String>>);
+
+impl Topics {
+    fn from_query(query: TopicQuery) -> Self {
+        Topics(query.topics.map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()))
+    }
+
+    fn allows(&self, event: &Event) -> bool {
+        self.0.as_ref().is_none_or(|topics| topics.iter().any(|t| t == event.topic()))
+    }
+}
+
+/// The current track first, then everything published from here on.
+fn events_for(bus: &EventBus, topics: Topics) -> impl Stream<Item = Event> + use<> {
+    // Subscribe before taking the snapshot so a change published in between
+    // is delivered (at worst twice) rather than lost.
+    let rx = bus.subscribe();
+    let current = bus.now_playing().map(Event::NowPlaying);
+    let live = stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(RecvError::Lagged(missed)) => tracing::warn!("Live listener skipped {} events", missed),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    stream::iter(current)
+        .chain(live)
+        .filter(move |event| future::ready(topics.allows(event)))
+}
+
+const MAX_HISTORY: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+    /// Only plays that started before this Unix timestamp, for paging back.
+    pub before: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayResponse {
+    pub id: i64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub show_id: Option<String>,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    pub peak_listeners: Option<i32>,
+}
+
+pub async fn history(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<PlayResponse>>, (StatusCode, String)> {
+    let rows = sqlx::query_as!(
+        PlayResponse,
+        r#"
+        SELECT id, title, artist, show_id, started_at, ended_at, peak_listeners
+        FROM public.play_history
+        WHERE started_at < $1
+        ORDER BY started_at DESC, id DESC
+        LIMIT $2
+        "#,
+        query.before.unwrap_or(i64::MAX),
+        query.limit.unwrap_or(20).clamp(1, MAX_HISTORY),
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?;
+
+    Ok(Json(rows))
+}
+
+pub async fn now_playing(
+    Extension(bus): Extension<EventBus>,
+) -> Json<Option<NowPlaying>> {
+    Json(bus.now_playing())
+}
+
+pub async fn events(
+    Extension(bus): Extension<EventBus>,
+    Query(query): Query<TopicQuery>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let stream = events_for(&bus, Topics::from_query(query)).map(|event| {
+        Ok(SseEvent::default()
+            .event(event.topic())
+            .json_data(&event)
+            .unwrap_or_else(|_| SseEvent::default().comment("unserializable event")))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn socket(
+    ws: WebSocketUpgrade,
+    Extension(bus): Extension<EventBus>,
+    Query(query): Query<TopicQuery>,
+) -> Response {
+    let topics = Topics::from_query(query);
+    ws.on_upgrade(move |socket| forward(socket, bus, topics))
+}
+
+async fn forward(mut socket: WebSocket, bus: EventBus, topics: Topics) {
+    let events = events_for(&bus, topics);
+    tokio::pin!(events);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+                let Ok(text) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                // Pings are answered by the protocol layer; anything else from the client is ignored.
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
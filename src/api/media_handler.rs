@@ -0,0 +1,81 @@
+use axum::{Extension, Json};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::media::backfill::{self, StartError, DEFAULT_CONCURRENCY};
+use crate::media::blobs;
+use crate::media::fetch::MediaKind;
+use crate::media::storage::SharedStorage;
+
+#[derive(Debug, Serialize)]
+pub struct GcResponse {
+    pub deleted: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BackfillData {
+    /// `image` and/or `audio`; both when omitted.
+    pub kinds: Option<Vec<String>>,
+    pub concurrency: Option<usize>,
+    /// Continues an interrupted backfill, skipping the rows it already failed on.
+    pub resume: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackfillAccepted {
+    pub id: String,
+    pub status_url: String,
+}
+
+pub async fn gc(
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<GcResponse>, (StatusCode, String)> {
+    let deleted = blobs::collect_garbage(&pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Json(GcResponse { deleted }))
+}
+
+/// Starts ingesting every un-fetched image/audio row in the background.
+pub async fn backfill(
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Json(payload): Json<BackfillData>,
+) -> Result<Response, (StatusCode, String)> {
+    let kinds = match payload.kinds {
+        None => vec![MediaKind::Image, MediaKind::Audio],
+        Some(labels) => labels
+            .iter()
+            .map(|k| match k.as_str() {
+                "image" => Ok(MediaKind::Image),
+                "audio" => Ok(MediaKind::Audio),
+                other => Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown kind: {}", other))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    let id = backfill::start(&pool, &kinds, payload.concurrency.unwrap_or(DEFAULT_CONCURRENCY), payload.resume.as_deref())
+        .await
+        .map_err(|e| match e {
+            StartError::AlreadyRunning => (StatusCode::CONFLICT, "A backfill is already running".to_string()),
+            StartError::NotResumable => (StatusCode::NOT_FOUND, "No interrupted backfill with that id".to_string()),
+            StartError::Database(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Backfill start failed: {}", e)),
+        })?;
+
+    let run_id = id.clone();
+    tokio::spawn(async move {
+        if let Err(e) = backfill::run(&pool, &storage, &run_id).await {
+            tracing::error!("Backfill {} interrupted: {}", run_id, e);
+        }
+    });
+
+    let status_url = format!("/media/backfill/{}", id);
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, status_url.clone())],
+        Json(BackfillAccepted { id, status_url }),
+    )
+        .into_response())
+}
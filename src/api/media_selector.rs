@@ -0,0 +1,17 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use sqlx::PgPool;
+use crate::media::backfill::{self, Report};
+
+/// Progress of a backfill and, once it is done, its failures grouped by reason.
+pub async fn backfill(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> Result<Json<Report>, (StatusCode, String)> {
+    backfill::report(&pool, &id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Backfill not found".into()))
+}
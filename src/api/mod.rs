@@ -1,6 +1,29 @@
 pub mod auth_handler;
+pub mod email_handler;
+pub mod session_handler;
+pub mod session_selector;
+pub mod user_selector;
+pub mod user_handler;
+pub mod identity_handler;
+pub mod identity_selector;
+pub mod api_key_handler;
+pub mod api_key_selector;
 pub mod article_handler;
 pub mod glossary_handler;
 pub mod image_handler;
+pub mod image_selector;
 pub mod audio_handler;
+pub mod audio_selector;
+pub mod media_handler;
+pub mod media_selector;
+pub mod job_selector;
+pub mod transcript_handler;
+pub mod transcript_selector;
+pub mod search_selector;
+pub mod episode_handler;
+pub mod podcast_feed;
+pub mod schedule_handler;
+pub mod schedule_selector;
+pub mod live_handler;
+pub mod live_selector;
 pub mod glossary_selector;
\ No newline at end of file
@@ -0,0 +1,361 @@
+use axum::Extension;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::env;
+use std::fmt::Write;
+
+/// Characters XML 1.0 does not allow even as references: C0 controls other than
+/// tab and newlines, U+FFFE and U+FFFF. A single one in a title makes podcast apps
+/// reject the whole feed.
+fn is_illegal(c: char) -> bool {
+    (c < ' ' && !matches!(c, '\t' | '\n' | '\r')) || c == '\u{FFFE}' || c == '\u{FFFF}'
+}
+
+/// Drops characters the feed cannot carry. Episode text is cleaned on the way in
+/// too, since Postgres rejects NUL in text columns.
+pub fn strip_illegal(value: &str) -> String {
+    value.chars().filter(|c| !is_illegal(*c)).collect()
+}
+
+/// Escapes markup and drops characters the feed cannot carry.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if is_illegal(c) => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn rfc2822(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc2822()
+}
+
+fn setting(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+/// Public base URL used for enclosure and feed links, e.g. `https://api.reveriontech.com`.
+pub fn public_url() -> String {
+    setting("PUBLIC_URL", "http://localhost:5000").trim_end_matches('/').to_string()
+}
+
+/// Podcast RSS 2.0 feed with iTunes and Podcasting 2.0 tags. Show-level
+/// details come from `PODCAST_*` settings; episodes from `public.episodes`.
+pub async fn feed(
+    Extension(pool): Extension<PgPool>,
+) -> Result<Response, (StatusCode, String)> {
+    let episodes = sqlx::query!(
+        r#"
+        SELECT
+            e.id,
+            e.audio_id,
+            e.title,
+            e.description,
+            e.season,
+            e.episode,
+            e.episode_type,
+            e.explicit,
+            e.artwork_url,
+            e.published_at,
+            a.duration_ms,
+            b.size AS "size?",
+            b.mime AS "mime?",
+            t.language AS "transcript_language?",
+            (c.audio_id IS NOT NULL) AS "has_chapters!"
+        FROM public.episodes e
+        JOIN public.audio a ON a.id = e.audio_id
+        LEFT JOIN public.media_blobs b ON b.hash = a.content_hash
+        LEFT JOIN public.audio_transcripts t ON t.audio_id = e.audio_id
+        LEFT JOIN public.audio_chapters c ON c.audio_id = e.audio_id
+        WHERE e.published_at <= $1
+        ORDER BY e.published_at DESC
+        "#,
+        Utc::now().timestamp()
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?;
+
+    let base = public_url();
+    let title = setting("PODCAST_TITLE", "The Crypto Radio");
+    let author = setting("PODCAST_AUTHOR", &title);
+    let explicit = setting("PODCAST_EXPLICIT", "false");
+
+    let mut xml = String::new();
+    let _ = write!(
+        xml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:podcast="https://podcastindex.org/namespace/1.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel>
+<title>{title}</title>
+<link>{link}</link>
+<atom:link href="{base}/podcast.xml" rel="self" type="application/rss+xml"/>
+<description>{description}</description>
+<language>{language}</language>
+<itunes:author>{author}</itunes:author>
+<itunes:explicit>{explicit}</itunes:explicit>
+<itunes:type>{show_type}</itunes:type>
+<itunes:category text="{category}"/>
+<itunes:owner><itunes:name>{author}</itunes:name><itunes:email>{email}</itunes:email></itunes:owner>
+"#,
+        title = escape(&title),
+        link = escape(&setting("PODCAST_LINK", &base)),
+        base = escape(&base),
+        description = escape(&setting("PODCAST_DESCRIPTION", "")),
+        language = escape(&setting("PODCAST_LANGUAGE", "en")),
+        author = escape(&author),
+        explicit = escape(&explicit),
+        show_type = escape(&setting("PODCAST_TYPE", "episodic")),
+        category = escape(&setting("PODCAST_CATEGORY", "Technology")),
+        email = escape(&setting("PODCAST_OWNER_EMAIL", "")),
+    );
+
+    if let Ok(image) = env::var("PODCAST_IMAGE_URL") {
+        let _ = writeln!(xml, r#"<itunes:image href="{0}"/>"#, escape(&image));
+        let _ = writeln!(xml, "<image><url>{0}</url><title>{1}</title><link>{2}</link></image>", escape(&image), escape(&title), escape(&base));
+    }
+    if let Ok(guid) = env::var("PODCAST_GUID") {
+        let _ = writeln!(xml, "<podcast:guid>{}</podcast:guid>", escape(&guid));
+    }
+    let _ = writeln!(xml, "<podcast:locked>{}</podcast:locked>", escape(&setting("PODCAST_LOCKED", "no")));
+
+    for ep in episodes {
+        let _ = writeln!(xml, "<item>");
+        let _ = writeln!(xml, "<title>{}</title>", escape(&ep.title));
+        let _ = writeln!(xml, "<itunes:title>{}</itunes:title>", escape(&ep.title));
+        let _ = writeln!(xml, "<description>{}</description>", escape(&ep.description));
+        let _ = writeln!(xml, r#"<guid isPermaLink="false">{}</guid>"#, escape(&ep.id));
+        let _ = writeln!(xml, "<pubDate>{}</pubDate>", rfc2822(ep.published_at));
+        let _ = writeln!(
+            xml,
+            r#"<enclosure url="{}/audio/{}" length="{}" type="{}"/>"#,
+            escape(&base),
+            escape(&ep.audio_id),
+            ep.size.unwrap_or(0),
+            escape(ep.mime.as_deref().unwrap_or("audio/mpeg")),
+        );
+        if let Some(ms) = ep.duration_ms {
+            let _ = writeln!(xml, "<itunes:duration>{}</itunes:duration>", ms / 1000);
+        }
+        let _ = writeln!(xml, "<itunes:episodeType>{}</itunes:episodeType>", escape(&ep.episode_type));
+        let _ = writeln!(xml, "<itunes:explicit>{}</itunes:explicit>", ep.explicit);
+        if let Some(season) = ep.season {
+            let _ = writeln!(xml, "<itunes:season>{0}</itunes:season>\n<podcast:season>{0}</podcast:season>", season);
+        }
+        if let Some(number) = ep.episode {
+            let _ = writeln!(xml, "<itunes:episode>{0}</itunes:episode>\n<podcast:episode>{0}</podcast:episode>", number);
+        }
+        if let Some(artwork) = ep.artwork_url {
+            let _ = writeln!(xml, r#"<itunes:image href="{}"/>"#, escape(&artwork));
+        }
+        if let Some(language) = ep.transcript_language {
+            let _ = writeln!(
+                xml,
+                r#"<podcast:transcript url="{}/audio/{}/transcript?format=vtt" type="text/vtt" language="{}"/>"#,
+                escape(&base),
+                escape(&ep.audio_id),
+                escape(&language),
+            );
+        }
+        if ep.has_chapters {
+            let _ = writeln!(
+                xml,
+                r#"<podcast:chapters url="{}/audio/{}/chapters" type="application/json+chapters"/>"#,
+                escape(&base),
+                escape(&ep.audio_id),
+            );
+        }
+        let _ = writeln!(xml, "</item>");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+
+    Ok(([(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")], xml).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::media::blobs;
+    use crate::media::storage::postgres::PostgresStorage;
+    use axum::body::to_bytes;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[derive(Default)]
+    struct Element {
+        name: String,
+        attrs: HashMap<String, String>,
+        text: String,
+        children: Vec<Element>,
+    }
+
+    impl Element {
+        fn child(&self, name: &str) -> &Element {
+            self.children
+                .iter()
+                .find(|c| c.name == name)
+                .unwrap_or_else(|| panic!("<{}> has no <{}>", self.name, name))
+        }
+    }
+
+    fn unescape(text: &str) -> Result<String, String> {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find('&') {
+            out.push_str(&rest[..at]);
+            let end = rest[at..].find(';').ok_or("unterminated entity")? + at;
+            out.push(match &rest[at + 1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                other => return Err(format!("unknown entity &{};", other)),
+            });
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    /// Just enough of a strict XML parser for the feed: elements, attributes,
+    /// text and the predefined entities. Fails where a podcast app would.
+    fn parse(xml: &str) -> Result<Element, String> {
+        let legal = |c: char| {
+            matches!(c, '\t' | '\n' | '\r' | ' '..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+        };
+        if let Some(c) = xml.chars().find(|c| !legal(*c)) {
+            return Err(format!("illegal character {:?}", c));
+        }
+
+        let mut rest = xml.strip_prefix(r#"<?xml version="1.0" encoding="UTF-8"?>"#).ok_or("missing declaration")?;
+        let mut stack = vec![Element::default()];
+        while let Some(open) = rest.find('<') {
+            let text = unescape(&rest[..open])?;
+            stack.last_mut().unwrap().text.push_str(&text);
+            let close = rest[open..].find('>').ok_or("unterminated tag")? + open;
+            let tag = &rest[open + 1..close];
+            rest = &rest[close + 1..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                let element = stack.pop().filter(|e| e.name == name).ok_or(format!("unexpected </{}>", name))?;
+                stack.last_mut().ok_or("unbalanced tags")?.children.push(element);
+                continue;
+            }
+
+            let (tag, empty) = tag.strip_suffix('/').map_or((tag, false), |tag| (tag, true));
+            let (name, mut attrs) = tag.split_once(' ').unwrap_or((tag, ""));
+            let mut element = Element { name: name.to_string(), ..Default::default() };
+            while let Some((key, value)) = attrs.trim_start().split_once("=\"") {
+                let (value, tail) = value.split_once('"').ok_or("unterminated attribute")?;
+                element.attrs.insert(key.to_string(), unescape(value)?);
+                attrs = tail;
+            }
+            if !attrs.trim().is_empty() {
+                return Err(format!("malformed attributes in <{}>", name));
+            }
+            if empty {
+                stack.last_mut().unwrap().children.push(element);
+            } else {
+                stack.push(element);
+            }
+        }
+
+        let mut document = stack.pop().filter(|_| stack.is_empty()).ok_or("unclosed element")?;
+        if !rest.trim().is_empty() || document.children.len() != 1 {
+            return Err("expected a single root element".into());
+        }
+        Ok(document.children.remove(0))
+    }
+
+    #[test]
+    fn strips_characters_xml_cannot_carry() {
+        assert_eq!(strip_illegal("a\u{0}b\u{1f}c\u{FFFE}"), "abc");
+        assert_eq!(strip_illegal("tab\tnew\r\nline \u{e9}\u{1F399}"), "tab\tnew\r\nline \u{e9}\u{1F399}");
+        assert_eq!(escape("<a href=\"x\">\u{0}'&'</a>"), "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;");
+    }
+
+    #[tokio::test]
+    async fn renders_a_feed_that_parses_as_xml() {
+        let pool = test_pool().await;
+        let audio_id = Uuid::new_v4().to_string();
+        let episode_id = Uuid::new_v4().to_string();
+        let hash = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+
+        let mut tx = pool.begin().await.unwrap();
+        blobs::store(&pool, &mut tx, &PostgresStorage::new(pool.clone()), &hash, &[7; 4321], Some("audio/mpeg"))
+            .await
+            .unwrap();
+        sqlx::query!(
+            "INSERT INTO public.audio (id, url, content_hash, duration_ms) VALUES ($1, $1, $2, 3723456)",
+            audio_id,
+            hash
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO public.episodes (id, audio_id, title, description, season, episode, published_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, 2, 7, 0, 0, 0)
+            "#,
+            episode_id,
+            audio_id,
+            "Bits\u{8} & <Bytes>\u{1b}",
+            "Line one\nline two\u{FFFF}",
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        tx.commit().await.unwrap();
+
+        let resp = feed(Extension(pool.clone())).await.unwrap();
+        let xml = String::from_utf8(to_bytes(resp.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+        let rss = parse(&xml).unwrap();
+        assert_eq!(rss.name, "rss");
+
+        let item = rss
+            .child("channel")
+            .children
+            .iter()
+            .find(|e| e.name == "item" && e.child("guid").text == episode_id)
+            .expect("episode in the feed");
+        assert_eq!(item.child("title").text, "Bits & <Bytes>");
+        assert_eq!(item.child("description").text, "Line one\nline two");
+
+        let enclosure = &item.child("enclosure").attrs;
+        assert_eq!(enclosure["url"], format!("{}/audio/{}", public_url(), audio_id));
+        assert_eq!(enclosure["length"], "4321");
+        assert_eq!(enclosure["type"], "audio/mpeg");
+        assert_eq!(item.child("itunes:duration").text, "3723");
+        assert_eq!(item.child("itunes:season").text, "2");
+        assert_eq!(item.child("itunes:episode").text, "7");
+
+        sqlx::query!("DELETE FROM public.episodes WHERE id = $1", episode_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query!("DELETE FROM public.audio WHERE id = $1", audio_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        if let Some(backend) = blobs::release(&mut conn, &hash).await.unwrap() {
+            blobs::purge(&pool, &hash, &backend).await.unwrap();
+        }
+    }
+}
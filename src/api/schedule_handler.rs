@@ -0,0 +1,344 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::events::{Event, EventBus, ScheduleChanged};
+use crate::schedule;
+
+#[derive(Debug, Deserialize)]
+pub struct HostData {
+    /// Updates the host when set.
+    pub id: Option<String>,
+    pub name: String,
+    pub bio: Option<String>,
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostResponse {
+    pub id: String,
+    pub name: String,
+    pub bio: Option<String>,
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShowData {
+    /// Updates the show when set.
+    pub id: Option<String>,
+    pub title: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    /// Replaces the show's hosts.
+    #[serde(default)]
+    pub host_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShowResponse {
+    pub id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub host_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SlotData {
+    pub show_id: String,
+    /// 0 = Monday ... 6 = Sunday.
+    pub weekday: i16,
+    /// Local wall-clock start, `HH:MM`.
+    pub start: String,
+    pub duration_minutes: i32,
+    /// IANA zone name, e.g. `Europe/Berlin`.
+    pub timezone: String,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SlotResponse {
+    pub id: String,
+    pub show_id: String,
+    pub weekday: i16,
+    pub start_minute: i32,
+    pub duration_minutes: i32,
+    pub timezone: String,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OverrideData {
+    /// Leave empty to take the regular schedule off air for the period.
+    pub show_id: Option<String>,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverrideResponse {
+    pub id: String,
+    pub show_id: Option<String>,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    pub note: Option<String>,
+}
+
+async fn require_show(pool: &PgPool, show_id: &str) -> Result<(), (StatusCode, String)> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM public.shows WHERE id = $1) AS "exists!"
+        "#,
+        show_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Show lookup failed: {}", e)))?;
+
+    if exists {
+        Ok(())
+    } else {
+        Err((StatusCode::UNPROCESSABLE_ENTITY, "Unknown show_id".into()))
+    }
+}
+
+fn changed(bus: &EventBus, kind: &'static str, id: &str, deleted: bool) {
+    bus.publish(Event::ScheduleChanged(ScheduleChanged { kind, id: id.to_string(), deleted }));
+}
+
+pub async fn host(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Json(payload): Json<HostData>,
+) -> Result<Json<HostResponse>, (StatusCode, String)> {
+    let now = Utc::now().timestamp();
+
+    let row = sqlx::query_as!(
+        HostResponse,
+        r#"
+        INSERT INTO public.hosts (id, name, bio, image_url, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $5)
+        ON CONFLICT (id) DO UPDATE
+        SET name = EXCLUDED.name,
+            bio = EXCLUDED.bio,
+            image_url = EXCLUDED.image_url,
+            updated_at = EXCLUDED.updated_at
+        RETURNING id, name, bio, image_url
+        "#,
+        payload.id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+        payload.name,
+        payload.bio,
+        payload.image_url,
+        now,
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Host save failed: {}", e)))?;
+
+    changed(&bus, "host", &row.id, false);
+    Ok(Json(row))
+}
+
+pub async fn show(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Json(payload): Json<ShowData>,
+) -> Result<Json<ShowResponse>, (StatusCode, String)> {
+    let now = Utc::now().timestamp();
+    let id = payload.id.unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Transaction failed: {}", e)))?;
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO public.shows (id, title, description, image_url, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $5)
+        ON CONFLICT (id) DO UPDATE
+        SET title = EXCLUDED.title,
+            description = EXCLUDED.description,
+            image_url = EXCLUDED.image_url,
+            updated_at = EXCLUDED.updated_at
+        RETURNING id, title, description, image_url
+        "#,
+        id,
+        payload.title,
+        payload.description,
+        payload.image_url,
+        now,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Show save failed: {}", e)))?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM public.show_hosts WHERE show_id = $1
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Show save failed: {}", e)))?;
+
+    let host_ids = sqlx::query_scalar!(
+        r#"
+        INSERT INTO public.show_hosts (show_id, host_id)
+        SELECT $1, h.id FROM public.hosts h WHERE h.id = ANY($2)
+        RETURNING host_id
+        "#,
+        id,
+        &payload.host_ids,
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Show save failed: {}", e)))?;
+
+    if let Some(missing) = payload.host_ids.iter().find(|h| !host_ids.contains(h)) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown host_id: {}", missing)));
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Commit failed: {}", e)))?;
+
+    changed(&bus, "show", &row.id, false);
+    Ok(Json(ShowResponse {
+        id: row.id,
+        title: row.title,
+        description: row.description,
+        image_url: row.image_url,
+        host_ids,
+    }))
+}
+
+pub async fn slot(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Json(payload): Json<SlotData>,
+) -> Result<Json<SlotResponse>, (StatusCode, String)> {
+    if !(0..=6).contains(&payload.weekday) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "weekday must be 0 (Monday) to 6 (Sunday)".into()));
+    }
+    if payload.duration_minutes <= 0 {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "duration_minutes must be positive".into()));
+    }
+    let start_minute = schedule::parse_start(&payload.start).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    schedule::parse_timezone(&payload.timezone).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    if let (Some(from), Some(until)) = (payload.valid_from, payload.valid_until)
+        && until <= from
+    {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "valid_until must be after valid_from".into()));
+    }
+    require_show(&pool, &payload.show_id).await?;
+
+    let row = sqlx::query_as!(
+        SlotResponse,
+        r#"
+        INSERT INTO public.schedule_slots (id, show_id, weekday, start_minute, duration_minutes, timezone, valid_from, valid_until, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id, show_id, weekday, start_minute, duration_minutes, timezone, valid_from, valid_until
+        "#,
+        Uuid::new_v4().to_string(),
+        payload.show_id,
+        payload.weekday,
+        start_minute,
+        payload.duration_minutes,
+        payload.timezone,
+        payload.valid_from,
+        payload.valid_until,
+        Utc::now().timestamp(),
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Insert failed: {}", e)))?;
+
+    changed(&bus, "slot", &row.id, false);
+    Ok(Json(row))
+}
+
+pub async fn delete_slot(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public.schedule_slots WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Delete failed: {}", e)))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Slot not found".into()));
+    }
+    changed(&bus, "slot", &id, true);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn override_slot(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Json(payload): Json<OverrideData>,
+) -> Result<Json<OverrideResponse>, (StatusCode, String)> {
+    if payload.ends_at <= payload.starts_at {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "ends_at must be after starts_at".into()));
+    }
+    if let Some(show_id) = &payload.show_id {
+        require_show(&pool, show_id).await?;
+    }
+
+    let row = sqlx::query_as!(
+        OverrideResponse,
+        r#"
+        INSERT INTO public.schedule_overrides (id, show_id, starts_at, ends_at, note, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, show_id, starts_at, ends_at, note
+        "#,
+        Uuid::new_v4().to_string(),
+        payload.show_id,
+        payload.starts_at,
+        payload.ends_at,
+        payload.note,
+        Utc::now().timestamp(),
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Insert failed: {}", e)))?;
+
+    changed(&bus, "override", &row.id, false);
+    Ok(Json(row))
+}
+
+pub async fn delete_override(
+    Extension(pool): Extension<PgPool>,
+    Extension(bus): Extension<EventBus>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public.schedule_overrides WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Delete failed: {}", e)))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Override not found".into()));
+    }
+    changed(&bus, "override", &id, true);
+    Ok(StatusCode::NO_CONTENT)
+}
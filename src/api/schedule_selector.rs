@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::env;
+use axum::{Extension, Json};
+use axum::extract::Query;
+use axum::http::StatusCode;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::schedule::{self, Airing};
+
+const MAX_NEXT: usize = 50;
+/// How far ahead "up next" looks for airings.
+const NEXT_HORIZON_DAYS: i64 = 14;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostSummary {
+    pub id: String,
+    pub name: String,
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShowSummary {
+    pub id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub hosts: Vec<HostSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AiringResponse {
+    pub show: ShowSummary,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    pub slot_id: Option<String>,
+    pub override_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DaySchedule {
+    pub date: String,
+    pub airings: Vec<AiringResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WeekResponse {
+    pub timezone: String,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    pub days: Vec<DaySchedule>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NextQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WeekQuery {
+    /// Any day in the wanted week, `YYYY-MM-DD`; defaults to today.
+    pub date: Option<String>,
+    /// IANA zone the grid is laid out in; defaults to `SCHEDULE_TIMEZONE`, then UTC.
+    pub tz: Option<String>,
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e))
+}
+
+async fn airings(pool: &PgPool, from: i64, to: i64) -> Result<Vec<Airing>, (StatusCode, String)> {
+    schedule::load(pool, from, to)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Attaches show and host details to each airing.
+async fn describe(pool: &PgPool, airings: Vec<Airing>) -> Result<Vec<AiringResponse>, (StatusCode, String)> {
+    let ids: Vec<String> = airings.iter().map(|a| a.show_id.clone()).collect();
+
+    let shows = sqlx::query!(
+        r#"
+        SELECT id, title, description, image_url
+        FROM public.shows
+        WHERE id = ANY($1)
+        "#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+
+    let hosts = sqlx::query!(
+        r#"
+        SELECT sh.show_id, h.id, h.name, h.image_url
+        FROM public.show_hosts sh
+        JOIN public.hosts h ON h.id = sh.host_id
+        WHERE sh.show_id = ANY($1)
+        ORDER BY h.name
+        "#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+
+    let mut hosts_by_show: HashMap<String, Vec<HostSummary>> = HashMap::new();
+    for h in hosts {
+        hosts_by_show.entry(h.show_id).or_default().push(HostSummary {
+            id: h.id,
+            name: h.name,
+            image_url: h.image_url,
+        });
+    }
+
+    let shows: HashMap<String, _> = shows.into_iter().map(|s| (s.id.clone(), s)).collect();
+
+    Ok(airings
+        .into_iter()
+        .filter_map(|a| {
+            let s = shows.get(&a.show_id)?;
+            Some(AiringResponse {
+                show: ShowSummary {
+                    id: s.id.clone(),
+                    title: s.title.clone(),
+                    description: s.description.clone(),
+                    image_url: s.image_url.clone(),
+                    hosts: hosts_by_show.get(&s.id).cloned().unwrap_or_default(),
+                },
+                starts_at: a.starts_at,
+                ends_at: a.ends_at,
+                slot_id: a.slot_id,
+                override_id: a.override_id,
+            })
+        })
+        .collect())
+}
+
+/// What's on air right now, or `null`.
+pub async fn now(
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Option<AiringResponse>>, (StatusCode, String)> {
+    let current = schedule::on_air(&pool, Utc::now().timestamp())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Json(describe(&pool, current.into_iter().collect()).await?.into_iter().next()))
+}
+
+/// Upcoming airings starting after now.
+pub async fn next(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<NextQuery>,
+) -> Result<Json<Vec<AiringResponse>>, (StatusCode, String)> {
+    let now = Utc::now().timestamp();
+    let limit = query.limit.unwrap_or(5).clamp(1, MAX_NEXT);
+    let upcoming: Vec<Airing> = airings(&pool, now, now + NEXT_HORIZON_DAYS * 86_400)
+        .await?
+        .into_iter()
+        .filter(|a| a.starts_at > now)
+        .take(limit)
+        .collect();
+
+    Ok(Json(describe(&pool, upcoming).await?))
+}
+
+/// Monday-to-Sunday grid in the requested time zone.
+pub async fn week(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<WeekQuery>,
+) -> Result<Json<WeekResponse>, (StatusCode, String)> {
+    let tz_name = query
+        .tz
+        .or_else(|| env::var("SCHEDULE_TIMEZONE").ok())
+        .unwrap_or_else(|| "UTC".to_string());
+    let tz = schedule::parse_timezone(&tz_name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let date = match query.date {
+        Some(d) => NaiveDate::parse_from_str(&d, "%Y-%m-%d")
+            .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid date: {}", d)))?,
+        None => Utc::now().with_timezone(&tz).date_naive(),
+    };
+    let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+    let days: Vec<NaiveDate> = (0..7).map(|i| monday + Duration::days(i)).collect();
+
+    let bad_day = || (StatusCode::BAD_REQUEST, "Date out of range".to_string());
+    let from = schedule::day_start(tz, monday).ok_or_else(bad_day)?;
+    let to = schedule::day_start(tz, monday + Duration::days(7)).ok_or_else(bad_day)?;
+
+    let airings = describe(&pool, airings(&pool, from, to).await?).await?;
+
+    let mut grid: Vec<DaySchedule> = days
+        .iter()
+        .map(|d| DaySchedule { date: d.to_string(), airings: Vec::new() })
+        .collect();
+    for airing in airings {
+        // Shows carried over from Sunday night are listed on Monday.
+        let day = DateTime::from_timestamp(airing.starts_at.max(from), 0)
+            .map(|t| t.with_timezone(&tz).date_naive())
+            .and_then(|d| days.iter().position(|x| *x == d));
+        if let Some(i) = day {
+            grid[i].airings.push(airing);
+        }
+    }
+
+    Ok(Json(WeekResponse { timezone: tz_name, starts_at: from, ends_at: to, days: grid }))
+}
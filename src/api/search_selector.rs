@@ -0,0 +1,151 @@
+use axum::{Extension, Json};
+use axum::extract::Query;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+const SEARCH_LIMIT: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    /// `article` or `transcript`.
+    pub kind: Option<String>,
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub snippet: Option<String>,
+    pub rank: Option<f32>,
+}
+
+/// Full-text search across published articles and audio transcripts.
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)> {
+    if query.q.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Missing search query".into()));
+    }
+
+    let rows = search(&pool, &query.q, Utc::now().timestamp())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Search failed: {}", e)))?;
+
+    Ok(Json(rows))
+}
+
+/// Transcripts only match once an episode for their audio is published as of `now`.
+async fn search(pool: &PgPool, q: &str, now: i64) -> Result<Vec<SearchResult>, sqlx::Error> {
+    sqlx::query_as!(
+        SearchResult,
+        r#"
+        WITH q AS (SELECT websearch_to_tsquery('simple', $1) AS query)
+        SELECT kind, id, title, snippet, rank FROM (
+            SELECT
+                'article' AS kind,
+                a.id,
+                a.title,
+                ts_headline('simple', coalesce(a.content, ''), q.query) AS snippet,
+                ts_rank(
+                    to_tsvector('simple', coalesce(a.title, '') || ' ' || coalesce(a.subheading, '') || ' ' || coalesce(a.content, '')),
+                    q.query
+                ) AS rank
+            FROM public.articles a, q
+            WHERE to_tsvector('simple', coalesce(a.title, '') || ' ' || coalesce(a.subheading, '') || ' ' || coalesce(a.content, '')) @@ q.query
+              AND lower(a.ispublished) IN ('1', 'true')
+              AND lower(coalesce(a.isarchived, '0')) NOT IN ('1', 'true')
+            UNION ALL
+            SELECT
+                'transcript' AS kind,
+                t.audio_id AS id,
+                ep.title,
+                ts_headline('simple', t.body, q.query) AS snippet,
+                ts_rank(t.search, q.query) AS rank
+            FROM public.audio_transcripts t
+            CROSS JOIN q
+            CROSS JOIN LATERAL (
+                SELECT e.title FROM public.episodes e
+                WHERE e.audio_id = t.audio_id AND e.published_at <= $3
+                ORDER BY e.published_at DESC
+                LIMIT 1
+            ) ep
+            WHERE t.search @@ q.query
+        ) results
+        ORDER BY rank DESC
+        LIMIT $2
+        "#,
+        q,
+        SEARCH_LIMIT,
+        now,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn transcripts_wait_for_a_published_episode() {
+        let pool = test_pool().await;
+        let audio_id = Uuid::new_v4().to_string();
+        let word = format!("zq{}", Uuid::new_v4().simple());
+        let now = 1_700_000_000;
+
+        sqlx::query!("INSERT INTO public.audio (id, url) VALUES ($1, $1)", audio_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "INSERT INTO public.audio_transcripts (audio_id, language, cues, body, updated_at) VALUES ($1, 'en', '[]', $2, 0)",
+            audio_id,
+            format!("hello {}", word),
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let transcripts = || async { search(&pool, &word, now).await.unwrap() };
+
+        assert!(transcripts().await.is_empty(), "audio without an episode");
+
+        sqlx::query!(
+            r#"
+            INSERT INTO public.episodes (id, audio_id, title, description, published_at, created_at, updated_at)
+            VALUES ($1, $2, 'Scheduled', '', $3, 0, 0)
+            "#,
+            Uuid::new_v4().to_string(),
+            audio_id,
+            now + 60,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(transcripts().await.is_empty(), "episode scheduled for later");
+
+        sqlx::query!("UPDATE public.episodes SET published_at = $2 WHERE audio_id = $1", audio_id, now - 60)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let found = transcripts().await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind.as_deref(), Some("transcript"));
+        assert_eq!(found[0].id.as_deref(), Some(audio_id.as_str()));
+        assert_eq!(found[0].title.as_deref(), Some("Scheduled"));
+
+        sqlx::query!("DELETE FROM public.episodes WHERE audio_id = $1", audio_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query!("DELETE FROM public.audio WHERE id = $1", audio_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
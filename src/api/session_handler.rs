@@ -0,0 +1,108 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::session::{self, AuthUser, Device, REFRESH_COOKIE};
+
+#[derive(Debug, Serialize)]
+pub struct RefreshResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokedResponse {
+    pub revoked: u64,
+}
+
+/// Issues a new access token for the session in the refresh cookie and rotates the cookie,
+/// unless a concurrent refresh just did.
+/// A failed refresh clears the cookie so the client stops retrying it.
+pub async fn refresh(
+    Extension(pool): Extension<PgPool>,
+    device: Device,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let unauthorized = |message: &str| {
+        (StatusCode::UNAUTHORIZED, [(header::SET_COOKIE, session::clear_cookie())], message.to_string()).into_response()
+    };
+
+    let Some(cookie) = session::cookie(&headers, REFRESH_COOKIE) else {
+        return Ok(unauthorized("Missing refresh cookie"));
+    };
+
+    let Some((access, cookie)) = session::refresh(&pool, cookie, &device)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+    else {
+        return Ok(unauthorized("Session expired or revoked"));
+    };
+
+    Ok((
+        cookie.map(|cookie| [(header::SET_COOKIE, cookie)]),
+        Json(RefreshResponse {
+            access_token: access.token,
+            token_type: "Bearer",
+            expires_at: access.expires_at,
+        }),
+    )
+        .into_response())
+}
+
+/// Ends the session in the refresh cookie. Always clears the cookie, even if the session was already gone.
+pub async fn logout(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(cookie) = session::cookie(&headers, REFRESH_COOKIE) {
+        session::end(&pool, cookie)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+
+    Ok((StatusCode::NO_CONTENT, [(header::SET_COOKIE, session::clear_cookie())]).into_response())
+}
+
+/// Ends every session of the signed-in user, this one included.
+pub async fn logout_all(
+    Extension(pool): Extension<PgPool>,
+    user: AuthUser,
+) -> Result<Response, (StatusCode, String)> {
+    let revoked = session::revoke_all(&pool, &user.user_id, "logout_all")
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(([(header::SET_COOKIE, session::clear_cookie())], Json(RevokedResponse { revoked })).into_response())
+}
+
+/// Ends one of the signed-in user's own sessions, e.g. a lost device.
+pub async fn revoke(
+    Extension(pool): Extension<PgPool>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let revoked = session::revoke(&pool, &user.user_id, &id, "user")
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    if revoked {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, "Session not found".into()))
+    }
+}
+
+/// Ends every session of another user.
+pub async fn revoke_user(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> Result<Json<RevokedResponse>, (StatusCode, String)> {
+    let revoked = session::revoke_all(&pool, &id, "admin")
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Json(RevokedResponse { revoked }))
+}
@@ -0,0 +1,55 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::session::AuthUser;
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: i64,
+    /// Last time the session was refreshed.
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+async fn live_sessions(pool: &PgPool, user_id: &str, current: &str) -> Result<Vec<SessionResponse>, (StatusCode, String)> {
+    sqlx::query_as!(
+        SessionResponse,
+        r#"
+        SELECT id, user_agent, ip, created_at, last_used_at, expires_at, id = $2 AS "current!"
+        FROM public.sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > $3
+        ORDER BY last_used_at DESC
+        "#,
+        user_id,
+        current,
+        Utc::now().timestamp(),
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))
+}
+
+/// The signed-in user's live sessions, most recently used first.
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    user: AuthUser,
+) -> Result<Json<Vec<SessionResponse>>, (StatusCode, String)> {
+    Ok(Json(live_sessions(&pool, &user.user_id, user.session_id().unwrap_or_default()).await?))
+}
+
+/// Another user's live sessions.
+pub async fn user_sessions(
+    Extension(pool): Extension<PgPool>,
+    admin: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<SessionResponse>>, (StatusCode, String)> {
+    Ok(Json(live_sessions(&pool, &id, admin.session_id().unwrap_or_default()).await?))
+}
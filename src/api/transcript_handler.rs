@@ -0,0 +1,132 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::types::Json as JsonColumn;
+use crate::media::captions::{self, Chapter, Cue};
+
+#[derive(Debug, Deserialize)]
+pub struct TranscriptData {
+    /// `vtt` or `srt`.
+    pub format: String,
+    pub content: String,
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TranscriptResponse {
+    pub audio_id: String,
+    pub language: String,
+    pub cues: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChaptersData {
+    /// Chapters as JSON, or...
+    pub chapters: Option<Vec<Chapter>>,
+    /// ...a WebVTT chapter track.
+    pub vtt: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChaptersResponse {
+    pub audio_id: String,
+    pub chapters: Vec<Chapter>,
+}
+
+async fn audio_duration(pool: &PgPool, id: &str) -> Result<Option<i64>, (StatusCode, String)> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT duration_ms FROM public.audio
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Audio lookup failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "Audio not found".into()))
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    Json(payload): Json<TranscriptData>,
+) -> Result<Json<TranscriptResponse>, (StatusCode, String)> {
+    let duration_ms = audio_duration(&pool, &id).await?;
+
+    let cues = match payload.format.as_str() {
+        "vtt" => captions::parse_vtt(&payload.content),
+        "srt" => captions::parse_srt(&payload.content),
+        other => Err(format!("Unsupported transcript format: {}", other)),
+    }
+    .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    if let (Some(duration), Some(last)) = (duration_ms, cues.last())
+        && last.start_ms >= duration
+    {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Transcript runs past the end of the audio".into()));
+    }
+
+    let language = payload.language.unwrap_or_else(|| "en".to_string());
+    let body = captions::plain_text(&cues);
+    let count = cues.len();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.audio_transcripts (audio_id, language, cues, body, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (audio_id) DO UPDATE
+        SET language = EXCLUDED.language,
+            cues = EXCLUDED.cues,
+            body = EXCLUDED.body,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        id,
+        language,
+        JsonColumn(cues) as JsonColumn<Vec<Cue>>,
+        body,
+        Utc::now().timestamp(),
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Transcript save failed: {}", e)))?;
+
+    Ok(Json(TranscriptResponse { audio_id: id, language, cues: count }))
+}
+
+pub async fn chapters(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    Json(payload): Json<ChaptersData>,
+) -> Result<Json<ChaptersResponse>, (StatusCode, String)> {
+    let duration_ms = audio_duration(&pool, &id).await?;
+
+    let chapters = match (payload.chapters, payload.vtt) {
+        (Some(chapters), None) => Ok(chapters),
+        (None, Some(vtt)) => captions::chapters_from_vtt(&vtt),
+        _ => Err("Send either chapters or vtt".to_string()),
+    }
+    .and_then(|chapters| captions::normalize_chapters(chapters, duration_ms))
+    .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.audio_chapters (audio_id, chapters, updated_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (audio_id) DO UPDATE
+        SET chapters = EXCLUDED.chapters,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        id,
+        JsonColumn(&chapters) as JsonColumn<&Vec<Chapter>>,
+        Utc::now().timestamp(),
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Chapters save failed: {}", e)))?;
+
+    Ok(Json(ChaptersResponse { audio_id: id, chapters }))
+}
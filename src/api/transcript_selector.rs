@@ -0,0 +1,112 @@
+use axum::Extension;
+use axum::extract::{Path, Query};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::types::Json as JsonColumn;
+use crate::media::captions::{self, Chapter, Cue};
+
+#[derive(Debug, Deserialize)]
+pub struct FormatQuery {
+    pub format: Option<String>,
+}
+
+/// A chapter in the Podcasting 2.0 JSON chapters format.
+#[derive(Debug, Serialize)]
+pub struct PodcastChapter {
+    #[serde(rename = "startTime")]
+    pub start_time: f64,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub img: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChaptersDocument {
+    pub version: &'static str,
+    pub chapters: Vec<PodcastChapter>,
+}
+
+pub async fn selector(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    Query(query): Query<FormatQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let row = sqlx::query!(
+        r#"
+        SELECT language, cues AS "cues: JsonColumn<Vec<Cue>>"
+        FROM public.audio_transcripts
+        WHERE audio_id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "No transcript for this audio".into()))?;
+
+    let (content_type, body) = match query.format.as_deref() {
+        None | Some("vtt") => ("text/vtt; charset=utf-8", captions::to_vtt(&row.cues)),
+        Some("srt") => ("application/x-subrip; charset=utf-8", captions::to_srt(&row.cues)),
+        Some(other) => return Err((StatusCode::BAD_REQUEST, format!("Unsupported format: {}", other))),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LANGUAGE, row.language),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+pub async fn chapters(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    Query(query): Query<FormatQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let row = sqlx::query!(
+        r#"
+        SELECT c.chapters AS "chapters: JsonColumn<Vec<Chapter>>", a.duration_ms
+        FROM public.audio_chapters c
+        JOIN public.audio a ON a.id = c.audio_id
+        WHERE c.audio_id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "No chapters for this audio".into()))?;
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok((
+            [(header::CONTENT_TYPE, "application/json+chapters")],
+            Json(ChaptersDocument {
+                version: "1.2.0",
+                chapters: row
+                    .chapters
+                    .0
+                    .into_iter()
+                    .map(|c| PodcastChapter {
+                        start_time: c.start_ms as f64 / 1000.0,
+                        title: c.title,
+                        url: c.url,
+                        img: c.img,
+                    })
+                    .collect(),
+            }),
+        )
+            .into_response()),
+        Some("vtt") => Ok((
+            [(header::CONTENT_TYPE, "text/vtt; charset=utf-8")],
+            captions::chapters_to_vtt(&row.chapters, row.duration_ms),
+        )
+            .into_response()),
+        Some(other) => Err((StatusCode::BAD_REQUEST, format!("Unsupported format: {}", other))),
+    }
+}
@@ -0,0 +1,63 @@
+use axum::{Extension, Json};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::authz::{Role, StaffPolicy};
+use crate::session::AuthUser;
+
+#[derive(Debug, Deserialize)]
+pub struct RoleData {
+    pub role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleResponse {
+    pub id: String,
+    pub role: String,
+}
+
+/// Changes a user's role. Admins cannot demote themselves, so there is always one left.
+pub async fn role(
+    Extension(pool): Extension<PgPool>,
+    admin: AuthUser,
+    Path(id): Path<String>,
+    Json(payload): Json<RoleData>,
+) -> Result<Json<RoleResponse>, (StatusCode, String)> {
+    let role = Role::parse(&payload.role)
+        .ok_or((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown role: {}", payload.role)))?;
+    if id == admin.user_id && role != Role::Admin {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Admins cannot demote themselves".into()));
+    }
+    let staff = StaffPolicy::from_env();
+    if staff.restricts(role)
+        && !staff
+            .allows_user(&pool, &id)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("The {} role needs a sign-in from an allowed staff domain or address", role),
+        ));
+    }
+
+    let row = sqlx::query_as!(
+        RoleResponse,
+        r#"
+        UPDATE public.users SET role = $2, updated_at = $3
+        WHERE id = $1
+        RETURNING id, role
+        "#,
+        id,
+        role.as_str(),
+        Utc::now().timestamp(),
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Update failed: {}", e)))?
+    .ok_or((StatusCode::NOT_FOUND, "User not found".into()))?;
+
+    Ok(Json(row))
+}
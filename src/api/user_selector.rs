@@ -0,0 +1,37 @@
+use axum::{Extension, Json};
+use axum::http::StatusCode;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::session::AuthUser;
+
+#[derive(Debug, Serialize)]
+pub struct UserResponse {
+    pub id: String,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub fullname: Option<String>,
+    pub picture: Option<String>,
+    pub role: String,
+}
+
+/// The signed-in user's profile.
+pub async fn me(
+    Extension(pool): Extension<PgPool>,
+    user: AuthUser,
+) -> Result<Json<UserResponse>, (StatusCode, String)> {
+    let row = sqlx::query_as!(
+        UserResponse,
+        r#"
+        SELECT id, username, email, fullname, picture, role
+        FROM public.users
+        WHERE id = $1
+        "#,
+        user.user_id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Fetch failed: {}", e)))?
+    .ok_or((StatusCode::UNAUTHORIZED, "User no longer exists".into()))?;
+
+    Ok(Json(row))
+}
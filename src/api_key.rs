@@ -0,0 +1,113 @@
+//! API keys for machine clients such as the scraper and newsletter jobs.
+//!
+//! A key looks like `eng_<prefix>_<secret>` and is sent as
+//! `Authorization: Bearer <key>`. The prefix is stored in the clear so keys can
+//! be told apart in listings and logs; only a SHA-256 of the whole key is kept.
+//! A key acts as the user it belongs to, but only on the routes its scopes open.
+
+use chrono::Utc;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sqlx::PgPool;
+use crate::media::fetch::content_hash;
+
+pub const KEY_PREFIX: &str = "eng_";
+/// `last_used_at` is only written when it is at least this old, to spare a write per request.
+const LAST_USED_GRANULARITY: i64 = 60;
+
+/// Each scope and the routes it opens, as (method, route path).
+pub const SCOPES: &[(&str, &[(&str, &str)])] = &[
+    ("articles:write", &[("POST", "/article")]),
+    ("media:write", &[("POST", "/image"), ("POST", "/audio")]),
+    ("jobs:read", &[("GET", "/jobs/{id}")]),
+    ("glossary:write", &[("POST", "/glossary")]),
+    ("episodes:write", &[("POST", "/episode")]),
+    ("transcripts:write", &[("POST", "/audio/{id}/transcript"), ("POST", "/audio/{id}/chapters")]),
+    ("live:write", &[("POST", "/live/now-playing")]),
+];
+
+pub fn is_scope(name: &str) -> bool {
+    SCOPES.iter().any(|(scope, _)| *scope == name)
+}
+
+/// The scope needed to call `method path` with a key; `None` if keys may not use the route at all.
+pub fn scope_for(method: &str, path: &str) -> Option<&'static str> {
+    SCOPES
+        .iter()
+        .find(|(_, routes)| routes.iter().any(|(m, p)| *m == method && *p == path))
+        .map(|(scope, _)| *scope)
+}
+
+pub struct NewKey {
+    pub prefix: String,
+    pub key: String,
+    pub hash: String,
+}
+
+pub fn generate() -> NewKey {
+    let mut prefix = [0u8; 4];
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut prefix);
+    OsRng.fill_bytes(&mut secret);
+
+    let prefix = hex::encode(prefix);
+    let key = format!("{}{}_{}", KEY_PREFIX, prefix, hex::encode(secret));
+    NewKey { hash: content_hash(key.as_bytes()), prefix, key }
+}
+
+/// A key that checked out, with its owner's current role.
+pub struct KeyPrincipal {
+    pub id: String,
+    pub user_id: String,
+    pub role: String,
+    pub scopes: Vec<String>,
+}
+
+/// Looks up a presented key; `None` if it is unknown, revoked or expired.
+pub async fn authenticate(pool: &PgPool, key: &str) -> Result<Option<KeyPrincipal>, String> {
+    let Some((prefix, _)) = key.strip_prefix(KEY_PREFIX).and_then(|rest| rest.split_once('_')) else {
+        return Ok(None);
+    };
+    let now = Utc::now().timestamp();
+
+    let found = sqlx::query!(
+        r#"
+        SELECT k.id, k.user_id, k.scopes, k.last_used_at, u.role
+        FROM public.api_keys k
+        JOIN public.users u ON u.id = k.user_id
+        WHERE k.prefix = $1 AND k.key_hash = $2 AND k.revoked_at IS NULL
+          AND (k.expires_at IS NULL OR k.expires_at > $3)
+        "#,
+        prefix,
+        content_hash(key.as_bytes()),
+        now,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("API key lookup failed: {}", e))?;
+
+    let Some(found) = found else {
+        return Ok(None);
+    };
+
+    if found.last_used_at.is_none_or(|at| at <= now - LAST_USED_GRANULARITY) {
+        sqlx::query!(
+            r#"
+            UPDATE public.api_keys SET last_used_at = $2
+            WHERE id = $1
+            "#,
+            found.id,
+            now,
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| format!("API key update failed: {}", e))?;
+    }
+
+    Ok(Some(KeyPrincipal {
+        id: found.id,
+        user_id: found.user_id,
+        role: found.role,
+        scopes: found.scopes,
+    }))
+}
@@ -0,0 +1,256 @@
+//! Role-based authorization.
+//!
+//! Every user has one role; each role can do everything the ones below it can.
+//! Routes declare the minimum role they need with [`require_role`] as a route
+//! layer. A missing or invalid session answers `401`, a role too low `403`.
+//! API keys also need a scope covering the route; routes outside every scope
+//! are closed to them.
+//!
+//! Staff roles (editor and admin) can be limited to particular sign-ins:
+//! - `STAFF_HOSTED_DOMAINS`: Google Workspace domains (the `hd` claim of Google sign-ins) staff may sign in from
+//! - `STAFF_EMAILS`: individual verified addresses allowed regardless of domain
+//!
+//! With neither set, any sign-in may hold a staff role.
+
+use std::env;
+use std::fmt;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use sqlx::PgPool;
+use crate::api_key;
+use crate::oidc::GOOGLE_LABEL;
+use crate::session::{AuthUser, Credential};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Reader,
+    Author,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "reader" => Some(Role::Reader),
+            "author" => Some(Role::Author),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Reader => "reader",
+            Role::Author => "author",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub struct StaffPolicy {
+    domains: Vec<String>,
+    emails: Vec<String>,
+}
+
+impl StaffPolicy {
+    pub fn from_env() -> Self {
+        let list = |name: &str| -> Vec<String> {
+            env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim().to_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect()
+        };
+        StaffPolicy { domains: list("STAFF_HOSTED_DOMAINS"), emails: list("STAFF_EMAILS") }
+    }
+
+    pub fn restricts(&self, role: Role) -> bool {
+        role >= Role::Editor && !(self.domains.is_empty() && self.emails.is_empty())
+    }
+
+    /// Whether a sign-in through `provider` with these claims may act as staff.
+    /// Only Google's `hd` claim counts for domains, since an email's domain
+    /// says nothing about who manages the account.
+    pub fn allows(&self, provider: &str, hosted_domain: Option<&str>, email: Option<&str>, email_verified: bool) -> bool {
+        (provider == GOOGLE_LABEL && hosted_domain.is_some_and(|hd| self.domains.contains(&hd.to_lowercase())))
+            || (email_verified && email.is_some_and(|email| self.emails.contains(&email.to_lowercase())))
+    }
+
+    /// Whether any identity linked to `user_id` may act as staff.
+    pub async fn allows_user(&self, pool: &PgPool, user_id: &str) -> Result<bool, String> {
+        let identities = sqlx::query!(
+            r#"
+            SELECT provider, hosted_domain, email, email_verified FROM public.auth
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Identity lookup failed: {}", e))?;
+
+        Ok(identities
+            .iter()
+            .any(|i| self.allows(&i.provider, i.hosted_domain.as_deref(), i.email.as_deref(), i.email_verified)))
+    }
+}
+
+/// Lets the request through only for users with at least `required`, and
+/// leaves the resolved [`AuthUser`] in the request for the handler.
+pub async fn require_role(
+    State(required): State<Role>,
+    user: AuthUser,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    if user.role < required {
+        return Err((StatusCode::FORBIDDEN, format!("Requires the {} role", required)));
+    }
+    if let Credential::ApiKey { scopes, .. } = &user.credential {
+        let path = req.extensions().get::<MatchedPath>().map(MatchedPath::as_str).unwrap_or_default();
+        match api_key::scope_for(req.method().as_str(), path) {
+            Some(scope) if scopes.iter().any(|s| s == scope) => {}
+            Some(scope) => return Err((StatusCode::FORBIDDEN, format!("API key lacks the {} scope", scope))),
+            None => return Err((StatusCode::FORBIDDEN, "API keys cannot be used here".into())),
+        }
+    }
+    req.extensions_mut().insert(user);
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::post;
+    use axum::{middleware, Extension, Router};
+    use tower::ServiceExt;
+    use crate::db::test_pool;
+    use crate::session::{self, Device};
+
+    fn policy(domains: &[&str], emails: &[&str]) -> StaffPolicy {
+        let list = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        StaffPolicy { domains: list(domains), emails: list(emails) }
+    }
+
+    #[test]
+    fn restricts_only_staff_roles_and_only_when_configured() {
+        let open = policy(&[], &[]);
+        assert!([Role::Reader, Role::Author, Role::Editor, Role::Admin].iter().all(|r| !open.restricts(*r)));
+
+        for staff in [policy(&["example.com"], &[]), policy(&[], &["boss@example.com"])] {
+            assert!(!staff.restricts(Role::Reader));
+            assert!(!staff.restricts(Role::Author));
+            assert!(staff.restricts(Role::Editor));
+            assert!(staff.restricts(Role::Admin));
+        }
+    }
+
+    #[test]
+    fn allows_hosted_domains_only_from_google() {
+        let staff = policy(&["example.com"], &[]);
+        assert!(staff.allows("Google", Some("Example.com"), None, false));
+        assert!(!staff.allows("Google", Some("other.com"), None, false));
+        assert!(!staff.allows("Google", None, Some("someone@example.com"), true));
+        // Other issuers can claim any `hd`.
+        assert!(!staff.allows("Microsoft", Some("example.com"), None, false));
+        assert!(!staff.allows("Email", Some("example.com"), Some("someone@example.com"), true));
+    }
+
+    #[test]
+    fn allows_listed_addresses_only_when_verified() {
+        let staff = policy(&[], &["boss@example.com"]);
+        assert!(staff.allows("Microsoft", None, Some("Boss@Example.com"), true));
+        assert!(staff.allows("Email", None, Some("boss@example.com"), true));
+        assert!(!staff.allows("Google", None, Some("boss@example.com"), false));
+        assert!(!staff.allows("Google", Some("example.com"), Some("intern@example.com"), true));
+    }
+
+    fn app(pool: PgPool) -> Router {
+        Router::new()
+            .route("/article", post(|| async { "ok" }))
+            .route("/glossary", post(|| async { "ok" }))
+            .route("/settings", post(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(Role::Editor, require_role))
+            .layer(Extension(pool))
+    }
+
+    async fn call(pool: &PgPool, path: &str, token: Option<&str>, user: Option<AuthUser>) -> StatusCode {
+        let mut req = Request::post(path);
+        if let Some(token) = token {
+            req = req.header("authorization", format!("Bearer {}", token));
+        }
+        let mut req = req.body(Body::empty()).unwrap();
+        if let Some(user) = user {
+            req.extensions_mut().insert(user);
+        }
+        app(pool.clone()).oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn answers_401_without_a_valid_session() {
+        let pool = test_pool().await;
+        assert_eq!(call(&pool, "/article", None, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(&pool, "/article", Some("not-a-jwt"), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(&pool, "/article", Some("eng_unknown"), None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn checks_the_role_of_the_session_user() {
+        let pool = test_pool().await;
+        let user_id = sqlx::query_scalar!("INSERT INTO public.users DEFAULT VALUES RETURNING id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let (access, _) = session::create(&pool, &user_id, &Device { user_agent: None, ip: None }).await.unwrap();
+        let token = Some(access.token.as_str());
+
+        assert_eq!(call(&pool, "/article", token, None).await, StatusCode::FORBIDDEN);
+
+        // Roles are read per request, so a promotion applies to existing sessions.
+        sqlx::query!("UPDATE public.users SET role = 'editor' WHERE id = $1", user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(call(&pool, "/article", token, None).await, StatusCode::OK);
+
+        session::revoke_all(&pool, &user_id, "test").await.unwrap();
+        assert_eq!(call(&pool, "/article", token, None).await, StatusCode::UNAUTHORIZED);
+
+        sqlx::query!("DELETE FROM public.users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_api_keys_to_their_scopes() {
+        let pool = test_pool().await;
+        let key = |role, scopes: &[&str]| AuthUser {
+            user_id: "key-owner".into(),
+            role,
+            credential: Credential::ApiKey {
+                id: "key".into(),
+                scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            },
+        };
+
+        let writer = || Some(key(Role::Editor, &["articles:write"]));
+        assert_eq!(call(&pool, "/article", None, writer()).await, StatusCode::OK);
+        assert_eq!(call(&pool, "/glossary", None, writer()).await, StatusCode::FORBIDDEN);
+        assert_eq!(call(&pool, "/settings", None, writer()).await, StatusCode::FORBIDDEN);
+
+        // A scope does not lift the owner's role.
+        let reader = Some(key(Role::Reader, &["articles:write"]));
+        assert_eq!(call(&pool, "/article", None, reader).await, StatusCode::FORBIDDEN);
+    }
+}
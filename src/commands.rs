@@ -0,0 +1,180 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use crate::authz::{Role, StaffPolicy};
+use crate::media::backfill::{self, StartError, DEFAULT_CONCURRENCY};
+use crate::media::fetch::MediaKind;
+use crate::media::storage::{self, migrate};
+
+const USAGE: &str = "usage: engine [migrate-storage <from> <to> | backfill [image] [audio] [--concurrency <n>] [--resume <id>] | grant-role <user id or email> <role>]";
+
+/// Runs a one-off maintenance command instead of starting the server.
+pub async fn run(args: &[String], pool: &PgPool) -> Result<(), String> {
+    match args {
+        [cmd, from, to] if cmd == "migrate-storage" => {
+            let report = migrate::migrate(pool, from, to).await?;
+            println!("Moved {} blob(s) from {} to {}", report.moved, from, to);
+            for hash in &report.missing {
+                println!("Missing in {}: {}", from, hash);
+            }
+            Ok(())
+        }
+        [cmd, rest @ ..] if cmd == "backfill" => run_backfill(rest, pool).await,
+        [cmd, user, role] if cmd == "grant-role" => grant_role(pool, user, role).await,
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn run_backfill(args: &[String], pool: &PgPool) -> Result<(), String> {
+    let mut kinds = Vec::new();
+    let mut concurrency = DEFAULT_CONCURRENCY;
+    let mut resume = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "image" => kinds.push(MediaKind::Image),
+            "audio" => kinds.push(MediaKind::Audio),
+            "--concurrency" => {
+                concurrency = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?;
+            }
+            "--resume" => resume = Some(args.next().ok_or(USAGE)?.as_str()),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    if kinds.is_empty() {
+        kinds = vec![MediaKind::Image, MediaKind::Audio];
+    }
+
+    let storage = storage::from_env(pool)?;
+    let id = backfill::start(pool, &kinds, concurrency, resume).await.map_err(|e| match e {
+        StartError::AlreadyRunning => "A backfill is already running".to_string(),
+        StartError::NotResumable => "No interrupted backfill with that id".to_string(),
+        StartError::Database(e) => e,
+    })?;
+    println!("Backfill {}", id);
+
+    let outcome = backfill::run(pool, &storage, &id).await;
+
+    if let Some(report) = backfill::report(pool, &id).await? {
+        println!(
+            "{}: {} of {} ingested, {} failed",
+            report.status, report.succeeded, report.total, report.failed
+        );
+        for group in &report.failures {
+            println!("  {} x {} (HTTP {})", group.count, group.reason, group.status);
+            for example in &group.examples {
+                println!("      {}", example);
+            }
+        }
+    }
+    outcome.map_err(|e| format!("Backfill {} interrupted: {}; resume with --resume {}", id, e, id))
+}
+
+/// Sets a user's role from the command line, which is how the first admin is made.
+async fn grant_role(pool: &PgPool, user: &str, role: &str) -> Result<(), String> {
+    let role = Role::parse(role).ok_or_else(|| format!("Unknown role: {}", role))?;
+
+    let matches = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM public.users
+        WHERE id = $1 OR lower(email) = lower($1)
+        ORDER BY id
+        "#,
+        user
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Lookup failed: {}", e))?;
+
+    // An exact id wins; an email shared by several accounts has to be settled by id.
+    let id = match matches.iter().find(|id| *id == user) {
+        Some(id) => id,
+        None => match &matches[..] {
+            [] => return Err(format!("No user matches {}", user)),
+            [id] => id,
+            ids => return Err(format!("{} matches several users ({}); pass a user id", user, ids.join(", "))),
+        },
+    };
+
+    let staff = StaffPolicy::from_env();
+    if staff.restricts(role) && !staff.allows_user(pool, id).await? {
+        return Err(format!("{} has no sign-in from an allowed staff domain or address", id));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE public.users SET role = $2, updated_at = $3
+        WHERE id = $1
+        "#,
+        id,
+        role.as_str(),
+        Utc::now().timestamp(),
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Update failed: {}", e))?;
+    println!("{} is now {}", id, role);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use uuid::Uuid;
+
+    async fn user(pool: &PgPool, email: &str) -> String {
+        sqlx::query_scalar!("INSERT INTO public.users (email) VALUES ($1) RETURNING id", email)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn role_of(pool: &PgPool, id: &str) -> String {
+        sqlx::query_scalar!("SELECT role FROM public.users WHERE id = $1", id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn grants_by_id_or_unambiguous_email() {
+        let pool = test_pool().await;
+        let email = format!("grant-{}@example.com", Uuid::new_v4());
+        let id = user(&pool, &email).await;
+
+        grant_role(&pool, &email.to_uppercase(), "author").await.unwrap();
+        assert_eq!(role_of(&pool, &id).await, "author");
+
+        grant_role(&pool, &id, "reader").await.unwrap();
+        assert_eq!(role_of(&pool, &id).await, "reader");
+
+        assert!(grant_role(&pool, &id, "superuser").await.is_err());
+        assert!(grant_role(&pool, "nobody@example.com.invalid", "author").await.is_err());
+
+        sqlx::query!("DELETE FROM public.users WHERE id = $1", id).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_emails_shared_by_several_users() {
+        let pool = test_pool().await;
+        let email = format!("shared-{}@example.com", Uuid::new_v4());
+        let first = user(&pool, &email).await;
+        let second = user(&pool, &email).await;
+
+        let err = grant_role(&pool, &email, "author").await.unwrap_err();
+        assert!(err.contains(&first) && err.contains(&second), "{}", err);
+        assert_eq!(role_of(&pool, &first).await, "reader");
+        assert_eq!(role_of(&pool, &second).await, "reader");
+
+        // The id settles it.
+        grant_role(&pool, &second, "author").await.unwrap();
+        assert_eq!(role_of(&pool, &first).await, "reader");
+        assert_eq!(role_of(&pool, &second).await, "author");
+
+        sqlx::query!("DELETE FROM public.users WHERE id = ANY($1)", &[first, second][..])
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
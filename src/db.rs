@@ -3,9 +3,27 @@ use std::env;
 
 pub async fn init_db_pool() -> PgPool {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgPoolOptions::new()
+    let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&db_url)
         .await
+        .expect("Failed to connect to PostgreSQL");
+
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("Failed to run database migrations");
+
+    pool
+}
+
+/// Connects to `DATABASE_URL` for tests; migrations are expected to be applied already.
+#[cfg(test)]
+pub async fn test_pool() -> PgPool {
+    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&db_url)
+        .await
         .expect("Failed to connect to PostgreSQL")
 }
@@ -70,15 +70,25 @@
 //     axum::serve(listener, app).await.unwrap();
 // }
 pub mod api;
+mod commands;
 mod routes;
 pub mod db;
-
-use axum::{body::Body, extract::State, http::{Method, Request, StatusCode}, middleware::{self, Next}, response::Response, Extension};
-// use axum::{http::Method, Extension};
+pub mod media;
+pub mod schedule;
+pub mod events;
+pub mod stream;
+pub mod jobs;
+pub mod session;
+pub mod authz;
+pub mod oidc;
+pub mod api_key;
+pub mod mailer;
+pub mod magic_link;
+
+use axum::{http::Method, Extension};
 use dotenvy::dotenv;
-use std::{env, sync::Arc};
-// use std::env;
-use tower_http::{cors::{CorsLayer, Any}, set_header::SetResponseHeaderLayer, trace::TraceLayer,};
+use std::env;
+use tower_http::{cors::{AllowOrigin, CorsLayer}, set_header::SetResponseHeaderLayer, trace::TraceLayer,};
 use db::init_db_pool;
 use routes::auth_routes;
 
@@ -103,14 +113,18 @@ async fn main() {
     tracing_subscriber::fmt::init();
     dotenv().ok();
 
-    let client_origin = env::var("CLIENT_URL").unwrap_or_else(|_| "http://localhost:4000".to_string());
-    let allowed_origin = Arc::new(client_origin.clone());
-
     let port = env::var("PORT").unwrap_or_else(|_| "5000".to_string());
 
+    // Browsers send the refresh cookie, so origins are listed rather than wildcarded.
+    let origins: Vec<axum::http::HeaderValue> = env::var("CLIENT_URL")
+        .unwrap_or_else(|_| "http://localhost:4000".to_string())
+        .split(',')
+        .filter_map(|origin| axum::http::HeaderValue::from_str(origin.trim().trim_end_matches('/')).ok())
+        .collect();
+
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
         .allow_headers([
             axum::http::header::CONTENT_TYPE,
             axum::http::header::AUTHORIZATION,
@@ -118,12 +132,33 @@ async fn main() {
             axum::http::header::ACCEPT_LANGUAGE,
             axum::http::header::ACCEPT_ENCODING,
         ])
-        .allow_credentials(false);
+        .allow_credentials(true);
 
     let db_pool = init_db_pool().await;
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = commands::run(&args, &db_pool).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let storage = media::storage::from_env(&db_pool).expect("Failed to configure media storage");
+    let providers = oidc::Providers::from_env().expect("Failed to configure sign-in providers");
+    let events = events::EventBus::new();
+    if let Err(e) = stream::restore(&db_pool, &events).await {
+        tracing::warn!("Could not restore now playing: {}", e);
+    }
+    stream::spawn_poller(db_pool.clone(), events.clone());
+    jobs::spawn_workers(db_pool.clone(), storage.clone());
+
     let app = auth_routes::routes()
         .layer(Extension(db_pool))
+        .layer(Extension(storage))
+        .layer(Extension(events))
+        .layer(Extension(providers))
         // .route_layer(middleware::from_fn_with_state(
         //     allowed_origin.clone()
         // ))
@@ -138,5 +173,7 @@ async fn main() {
     println!("Server running on http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .await
+        .unwrap();
 }
@@ -0,0 +1,107 @@
+//! In-process broadcast bus for live updates.
+//!
+//! Handlers publish events here; the SSE and WebSocket endpoints fan them out
+//! to every connected listener. Nothing is persisted: a listener that falls
+//! behind by more than `EVENT_BUFFER` events simply misses the oldest ones.
+
+use std::sync::{Arc, RwLock};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NowPlaying {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub show_id: Option<String>,
+    pub listeners: Option<i32>,
+    pub started_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Listeners {
+    pub listeners: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleChanged {
+    /// `host`, `show`, `slot` or `override`.
+    pub kind: &'static str,
+    pub id: String,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArticlePublished {
+    pub id: String,
+    pub title: String,
+    pub subheading: String,
+    pub language: String,
+    pub ismainpage: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    NowPlaying(NowPlaying),
+    Listeners(Listeners),
+    ScheduleChanged(ScheduleChanged),
+    ArticlePublished(ArticlePublished),
+}
+
+impl Event {
+    /// Topic name, used as the SSE event name and for `?topics=` filtering.
+    pub fn topic(&self) -> &'static str {
+        match self {
+            Event::NowPlaying(_) => "now_playing",
+            Event::Listeners(_) => "listeners",
+            Event::ScheduleChanged(_) => "schedule_changed",
+            Event::ArticlePublished(_) => "article_published",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+    /// Latest now-playing, replayed to listeners as soon as they connect.
+    now_playing: Arc<RwLock<Option<NowPlaying>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUFFER);
+        Self { tx, now_playing: Arc::new(RwLock::new(None)) }
+    }
+
+    /// Sends an event to every current listener. Having no listeners is fine.
+    pub fn publish(&self, event: Event) {
+        if let Ok(mut current) = self.now_playing.write() {
+            match &event {
+                Event::NowPlaying(np) => *current = Some(np.clone()),
+                Event::Listeners(l) => {
+                    if let Some(np) = current.as_mut() {
+                        np.listeners = Some(l.listeners);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        self.now_playing.read().ok().and_then(|np| np.clone())
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
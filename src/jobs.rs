@@ -0,0 +1,415 @@
+//! Postgres-backed background job queue.
+//!
+//! Jobs live in `public.jobs`. Workers claim them with `FOR UPDATE SKIP LOCKED`,
+//! so any number of workers (and engine instances) can share one queue. Failures
+//! the origin might recover from (5xx) are retried with exponential backoff;
+//! anything else fails the job straight away.
+//!
+//! Settings:
+//! - `JOB_WORKERS`: workers per process, default 2; `0` leaves jobs to other instances
+//! - `JOB_MAX_ATTEMPTS`: attempts before a job is marked failed, default 5
+//! - `JOB_BACKOFF_SECONDS`: delay before the first retry, doubled each time, default 10
+//! - `JOB_TIMEOUT_SECONDS`: running jobs not finished by then are picked up again (or failed,
+//!   once out of attempts), default 900
+
+use std::env;
+use std::time::Duration;
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::sync::Notify;
+use uuid::Uuid;
+use crate::api::{audio_handler, image_handler};
+use crate::media::storage::SharedStorage;
+
+pub const INGEST_IMAGE: &str = "ingest_image";
+pub const INGEST_AUDIO: &str = "ingest_audio";
+
+const IDLE_POLL: Duration = Duration::from_secs(2);
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+/// Wakes idle workers in this process as soon as something is enqueued.
+static WAKE: Notify = Notify::const_new();
+
+fn setting(name: &str, default: i64) -> i64 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+pub struct Enqueued {
+    pub id: String,
+    pub status: String,
+}
+
+/// Adds a job, or returns the pending one with the same `dedupe_key`.
+pub async fn enqueue(pool: &PgPool, kind: &str, payload: Value, dedupe_key: Option<&str>) -> Result<Enqueued, String> {
+    let now = Utc::now().timestamp();
+
+    // A pending duplicate can finish between the insert and the lookup; try again then.
+    for _ in 0..3 {
+        let inserted = sqlx::query_as!(
+            Enqueued,
+            r#"
+            INSERT INTO public.jobs (id, kind, payload, dedupe_key, max_attempts, run_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $6, $6)
+            ON CONFLICT (dedupe_key) WHERE status IN ('queued', 'running') DO NOTHING
+            RETURNING id, status
+            "#,
+            Uuid::new_v4().to_string(),
+            kind,
+            payload,
+            dedupe_key,
+            setting("JOB_MAX_ATTEMPTS", 5) as i32,
+            now,
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Enqueue failed: {}", e))?;
+
+        if let Some(job) = inserted {
+            WAKE.notify_one();
+            return Ok(job);
+        }
+
+        let pending = sqlx::query_as!(
+            Enqueued,
+            r#"
+            SELECT id, status FROM public.jobs
+            WHERE dedupe_key = $1 AND status IN ('queued', 'running')
+            "#,
+            dedupe_key
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Enqueue failed: {}", e))?;
+
+        if let Some(job) = pending {
+            return Ok(job);
+        }
+    }
+
+    Err("Enqueue failed: job keeps changing state".into())
+}
+
+struct Claimed {
+    id: String,
+    kind: String,
+    payload: Value,
+    attempts: i32,
+    max_attempts: i32,
+}
+
+/// Takes the next due job, including ones whose worker has gone quiet for longer than the timeout.
+async fn claim(pool: &PgPool) -> Result<Option<Claimed>, String> {
+    let now = Utc::now().timestamp();
+    let timeout = setting("JOB_TIMEOUT_SECONDS", 900);
+
+    // A job that keeps taking its worker down (one huge download, say) would
+    // otherwise be picked up again forever.
+    sqlx::query!(
+        r#"
+        UPDATE public.jobs
+        SET status = 'failed', last_error = $3, locked_at = NULL, updated_at = $1
+        WHERE status = 'running' AND locked_at < $2 AND attempts >= max_attempts
+        "#,
+        now,
+        now - timeout,
+        format!("No outcome within {} seconds on the last attempt", timeout),
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Claim failed: {}", e))?;
+
+    sqlx::query_as!(
+        Claimed,
+        r#"
+        UPDATE public.jobs
+        SET status = 'running', attempts = attempts + 1, locked_at = $1, updated_at = $1
+        WHERE id = (
+            SELECT id FROM public.jobs
+            WHERE (status = 'queued' AND run_at <= $1)
+               OR (status = 'running' AND locked_at < $2 AND attempts < max_attempts)
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, payload, attempts, max_attempts
+        "#,
+        now,
+        now - timeout,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Claim failed: {}", e))
+}
+
+async fn run(pool: &PgPool, storage: &SharedStorage, job: &Claimed) -> Result<Value, (StatusCode, String)> {
+    let url = job
+        .payload
+        .get("url")
+        .and_then(Value::as_str)
+        .ok_or((StatusCode::UNPROCESSABLE_ENTITY, "Job payload has no url".to_string()))?;
+
+    let result = match job.kind.as_str() {
+        INGEST_IMAGE => serde_json::to_value(image_handler::ingest(pool, storage, url).await?),
+        INGEST_AUDIO => serde_json::to_value(audio_handler::ingest(pool, storage, url).await?),
+        other => return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown job kind: {}", other))),
+    };
+    result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Result encoding failed: {}", e)))
+}
+
+fn backoff(attempts: i32) -> i64 {
+    let base = setting("JOB_BACKOFF_SECONDS", 10);
+    base.saturating_mul(1 << (attempts - 1).clamp(0, 20)).min(MAX_BACKOFF_SECONDS)
+}
+
+/// Records the outcome of `job`, returning `false` if the job has since been
+/// reclaimed by another worker, whose outcome wins.
+async fn finish(pool: &PgPool, job: &Claimed, outcome: Result<Value, (StatusCode, String)>) -> Result<bool, sqlx::Error> {
+    let now = Utc::now().timestamp();
+
+    let updated = match outcome {
+        Ok(result) => {
+            sqlx::query!(
+                r#"
+                UPDATE public.jobs
+                SET status = 'succeeded', result = $2, last_error = NULL, locked_at = NULL, updated_at = $3
+                WHERE id = $1 AND status = 'running' AND attempts = $4
+                "#,
+                job.id,
+                result,
+                now,
+                job.attempts,
+            )
+            .execute(pool)
+            .await?
+        }
+        Err((status, error)) => {
+            let retry = status.is_server_error() && job.attempts < job.max_attempts;
+            if retry {
+                tracing::warn!("Job {} attempt {} failed, retrying: {}", job.id, job.attempts, error);
+            } else {
+                tracing::warn!("Job {} failed: {}", job.id, error);
+            }
+
+            sqlx::query!(
+                r#"
+                UPDATE public.jobs
+                SET status = $2, run_at = $3, last_error = $4, result = $5, locked_at = NULL, updated_at = $6
+                WHERE id = $1 AND status = 'running' AND attempts = $7
+                "#,
+                job.id,
+                if retry { "queued" } else { "failed" },
+                now + if retry { backoff(job.attempts) } else { 0 },
+                error,
+                json!({ "status": status.as_u16() }),
+                now,
+                job.attempts,
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+    Ok(updated.rows_affected() > 0)
+}
+
+async fn work(pool: PgPool, storage: SharedStorage) {
+    loop {
+        match claim(&pool).await {
+            Ok(Some(job)) => {
+                let outcome = run(&pool, &storage, &job).await;
+                match finish(&pool, &job, outcome).await {
+                    Ok(true) => {}
+                    Ok(false) => tracing::warn!("Job {} was reclaimed before attempt {} finished", job.id, job.attempts),
+                    Err(e) => tracing::error!("Could not record outcome of job {}: {}", job.id, e),
+                }
+            }
+            Ok(None) => {
+                tokio::select! {
+                    _ = WAKE.notified() => {}
+                    _ = tokio::time::sleep(IDLE_POLL) => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!("{}", e);
+                tokio::time::sleep(IDLE_POLL).await;
+            }
+        }
+    }
+}
+
+pub fn spawn_workers(pool: PgPool, storage: SharedStorage) {
+    for _ in 0..setting("JOB_WORKERS", 2) {
+        tokio::spawn(work(pool.clone(), storage.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use tokio::sync::Mutex;
+
+    /// `claim` takes whatever is due, so tests touching the queue take turns.
+    static QUEUE: Mutex<()> = Mutex::const_new(());
+
+    struct State {
+        status: String,
+        attempts: i32,
+        run_at: i64,
+        last_error: Option<String>,
+        result: Option<Value>,
+    }
+
+    async fn state(pool: &PgPool, id: &str) -> State {
+        sqlx::query_as!(
+            State,
+            "SELECT status, attempts, run_at, last_error, result FROM public.jobs WHERE id = $1",
+            id
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn enqueue_test_job(pool: &PgPool, max_attempts: i32) -> String {
+        let job = enqueue(pool, "test", json!({}), None).await.unwrap();
+        sqlx::query!("UPDATE public.jobs SET max_attempts = $2 WHERE id = $1", job.id, max_attempts)
+            .execute(pool)
+            .await
+            .unwrap();
+        job.id
+    }
+
+    async fn claim_test_job(pool: &PgPool, id: &str) -> Claimed {
+        let claimed = claim(pool).await.unwrap().expect("a due job");
+        assert_eq!(claimed.id, id);
+        claimed
+    }
+
+    /// Makes the job due again, or its worker look dead if it is running.
+    async fn age(pool: &PgPool, id: &str) {
+        sqlx::query!("UPDATE public.jobs SET run_at = 0, locked_at = 0 WHERE id = $1", id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn delete(pool: &PgPool, id: &str) {
+        sqlx::query!("DELETE FROM public.jobs WHERE id = $1", id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_cap() {
+        assert_eq!([1, 2, 3, 4].map(backoff), [10, 20, 40, 80]);
+        assert_eq!(backoff(30), MAX_BACKOFF_SECONDS);
+    }
+
+    #[tokio::test]
+    async fn dedupes_pending_jobs_by_key() {
+        let _queue = QUEUE.lock().await;
+        let pool = test_pool().await;
+        let key = format!("test:{}", Uuid::new_v4());
+
+        let first = enqueue(&pool, "test", json!({}), Some(&key)).await.unwrap();
+        let again = enqueue(&pool, "test", json!({}), Some(&key)).await.unwrap();
+        assert_eq!(again.id, first.id);
+        assert_eq!(again.status, "queued");
+
+        // Once the job is done the key is free again.
+        sqlx::query!("UPDATE public.jobs SET status = 'succeeded' WHERE id = $1", first.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let next = enqueue(&pool, "test", json!({}), Some(&key)).await.unwrap();
+        assert_ne!(next.id, first.id);
+
+        delete(&pool, &first.id).await;
+        delete(&pool, &next.id).await;
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_with_backoff_until_out_of_attempts() {
+        let _queue = QUEUE.lock().await;
+        let pool = test_pool().await;
+        let id = enqueue_test_job(&pool, 2).await;
+
+        let job = claim_test_job(&pool, &id).await;
+        assert_eq!(job.attempts, 1);
+        let failed_at = Utc::now().timestamp();
+        assert!(finish(&pool, &job, Err((StatusCode::BAD_GATEWAY, "Download failed: HTTP 502".into()))).await.unwrap());
+
+        let after_first = state(&pool, &id).await;
+        assert_eq!(after_first.status, "queued");
+        assert!(after_first.run_at >= failed_at + backoff(1));
+        assert!(claim(&pool).await.unwrap().is_none(), "not due before the backoff");
+
+        age(&pool, &id).await;
+        let job = claim_test_job(&pool, &id).await;
+        assert_eq!(job.attempts, 2);
+        assert!(finish(&pool, &job, Err((StatusCode::BAD_GATEWAY, "Download failed: HTTP 502".into()))).await.unwrap());
+
+        let after_last = state(&pool, &id).await;
+        assert_eq!((after_last.status.as_str(), after_last.attempts), ("failed", 2));
+        assert_eq!(after_last.last_error.as_deref(), Some("Download failed: HTTP 502"));
+        assert_eq!(after_last.result, Some(json!({ "status": 502 })));
+
+        delete(&pool, &id).await;
+    }
+
+    #[tokio::test]
+    async fn fails_client_errors_without_retrying() {
+        let _queue = QUEUE.lock().await;
+        let pool = test_pool().await;
+        let id = enqueue_test_job(&pool, 5).await;
+
+        let job = claim_test_job(&pool, &id).await;
+        assert!(finish(&pool, &job, Err((StatusCode::UNPROCESSABLE_ENTITY, "Not an image".into()))).await.unwrap());
+        let after = state(&pool, &id).await;
+        assert_eq!((after.status.as_str(), after.attempts), ("failed", 1));
+
+        delete(&pool, &id).await;
+    }
+
+    #[tokio::test]
+    async fn reclaims_stale_jobs_and_discards_the_late_outcome() {
+        let _queue = QUEUE.lock().await;
+        let pool = test_pool().await;
+        let id = enqueue_test_job(&pool, 2).await;
+
+        let first = claim_test_job(&pool, &id).await;
+        age(&pool, &id).await;
+        let second = claim_test_job(&pool, &id).await;
+        assert_eq!(second.attempts, 2);
+
+        assert!(!finish(&pool, &first, Ok(json!("late"))).await.unwrap());
+        assert_eq!(state(&pool, &id).await.status, "running");
+
+        assert!(finish(&pool, &second, Ok(json!("done"))).await.unwrap());
+        let after = state(&pool, &id).await;
+        assert_eq!((after.status.as_str(), after.result), ("succeeded", Some(json!("done"))));
+
+        delete(&pool, &id).await;
+    }
+
+    #[tokio::test]
+    async fn fails_stale_jobs_on_their_last_attempt() {
+        let _queue = QUEUE.lock().await;
+        let pool = test_pool().await;
+        let id = enqueue_test_job(&pool, 1).await;
+
+        let job = claim_test_job(&pool, &id).await;
+        age(&pool, &id).await;
+        assert!(claim(&pool).await.unwrap().is_none());
+
+        let after = state(&pool, &id).await;
+        assert_eq!((after.status.as_str(), after.attempts), ("failed", 1));
+        assert!(after.last_error.unwrap().contains("last attempt"));
+        assert!(!finish(&pool, &job, Ok(json!("late"))).await.unwrap());
+
+        delete(&pool, &id).await;
+    }
+}
@@ -0,0 +1,254 @@
+//! Passwordless sign-in by emailed link.
+//!
+//! `POST /email` mails a link carrying a random token; the page it opens posts
+//! the token to `POST /email/verify`, which signs the address in as an
+//! identity with provider "Email". Tokens are single-use and short-lived, and
+//! only their SHA-256 is stored.
+//!
+//! Settings:
+//! - `MAGIC_LINK_URL`: page the link opens, default `<first CLIENT_URL>/auth/email`
+//! - `MAGIC_LINK_TTL_SECONDS`: default 900
+//! - `MAGIC_LINK_MAX_PER_HOUR`: links sent to one address per hour, default 5
+
+use std::env;
+use chrono::Utc;
+use lettre::Address;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::mailer;
+use crate::media::fetch::content_hash;
+
+pub const PROVIDER: &str = "Email";
+
+pub enum RequestError {
+    InvalidEmail,
+    Throttled,
+    Failed(String),
+}
+
+fn setting(name: &str, default: i64) -> i64 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn link_base() -> String {
+    env::var("MAGIC_LINK_URL").unwrap_or_else(|_| {
+        let client = env::var("CLIENT_URL").unwrap_or_else(|_| "http://localhost:4000".to_string());
+        let first = client.split(',').next().unwrap_or_default().trim().trim_end_matches('/');
+        format!("{}/auth/email", first)
+    })
+}
+
+/// Lowercases and checks an address; `None` if it is not one.
+pub fn normalize(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    email.parse::<Address>().ok().map(|_| email)
+}
+
+pub fn ttl() -> i64 {
+    setting("MAGIC_LINK_TTL_SECONDS", 900)
+}
+
+/// Records a new token for `email` and mails the link.
+pub async fn request(pool: &PgPool, email: &str, ip: Option<&str>) -> Result<(), RequestError> {
+    let email = normalize(email).ok_or(RequestError::InvalidEmail)?;
+    let db = |e: sqlx::Error| RequestError::Failed(format!("Magic link failed: {}", e));
+    let now = Utc::now().timestamp();
+
+    let mut tx = pool.begin().await.map_err(db)?;
+
+    // Held until commit, so concurrent requests for one address take turns
+    // counting and cannot all slip under the limit.
+    sqlx::query!(
+        r#"
+        SELECT 1 AS locked FROM pg_advisory_xact_lock(hashtext($1))
+        "#,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    let recent = sqlx::query_scalar!(
+        r#"
+        SELECT count(*) AS "count!" FROM public.magic_links
+        WHERE lower(email) = $1 AND created_at > $2
+        "#,
+        email,
+        now - 3600,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    if recent >= setting("MAGIC_LINK_MAX_PER_HOUR", 5) {
+        return Err(RequestError::Throttled);
+    }
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let id = Uuid::new_v4().to_string();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.magic_links (id, email, token_hash, ip, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        id,
+        email,
+        content_hash(token.as_bytes()),
+        ip,
+        now,
+        now + ttl(),
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    tx.commit().await.map_err(db)?;
+
+    let body = format!(
+        "Use this link to sign in. It works once and expires in {} minutes.\n\n{}?token={}\n\nIf you did not ask to sign in, you can ignore this email.\n",
+        ttl() / 60,
+        link_base(),
+        token,
+    );
+
+    if let Err(e) = mailer::send(&email, "Your sign-in link", body).await {
+        // An undelivered link should not count against the address.
+        sqlx::query!(
+            r#"
+            DELETE FROM public.magic_links WHERE id = $1
+            "#,
+            id
+        )
+        .execute(pool)
+        .await
+        .map_err(db)?;
+        return Err(RequestError::Failed(e));
+    }
+    Ok(())
+}
+
+/// Uses up a token, returning the address it was sent to; `None` if it is unknown, expired or already used.
+pub async fn consume(pool: &PgPool, token: &str) -> Result<Option<String>, String> {
+    let now = Utc::now().timestamp();
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE public.magic_links SET used_at = $2
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+        RETURNING email
+        "#,
+        content_hash(token.trim().as_bytes()),
+        now,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Magic link lookup failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts every message and keeps its DATA section.
+    async fn smtp_sink() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages: Arc<Mutex<Vec<String>>> = Arc::default();
+        let inbox = messages.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let inbox = inbox.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 sink\r\n").await.unwrap();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply: &[u8] = match line.to_uppercase().get(..4) {
+                            Some("EHLO") => b"250-sink\r\n250 8BITMIME\r\n",
+                            Some("DATA") => {
+                                write.write_all(b"354 go ahead\r\n").await.unwrap();
+                                let mut data = String::new();
+                                while let Ok(Some(line)) = lines.next_line().await {
+                                    if line == "." {
+                                        break;
+                                    }
+                                    data.push_str(&line);
+                                    data.push('\n');
+                                }
+                                inbox.lock().unwrap().push(data);
+                                b"250 queued\r\n"
+                            }
+                            Some("QUIT") => b"221 bye\r\n",
+                            _ => b"250 ok\r\n",
+                        };
+                        write.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+        (port, messages)
+    }
+
+    /// The token in the last link mailed to `email`, undoing quoted-printable encoding.
+    fn mailed_token(messages: &Mutex<Vec<String>>, email: &str) -> String {
+        let messages = messages.lock().unwrap();
+        let message = messages.iter().rev().find(|m| m.contains(email)).expect("link mailed");
+        let body = message.replace("=\n", "").replace("=3D", "=");
+        let start = body.find("?token=").expect("link in body") + "?token=".len();
+        body[start..start + 64].to_string()
+    }
+
+    async fn expire(pool: &PgPool, email: &str) {
+        sqlx::query!("UPDATE public.magic_links SET expires_at = 0 WHERE email = $1", email)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // One test, because the mail transport is process-wide and bound to the
+    // runtime it first ran on.
+    #[tokio::test]
+    async fn sends_single_use_expiring_throttled_links() {
+        let pool = test_pool().await;
+        let (port, messages) = smtp_sink().await;
+        mailer::use_test_transport(port);
+        let email = format!("link-{}@example.com", Uuid::new_v4().simple());
+
+        assert!(matches!(request(&pool, "not an address", None).await, Err(RequestError::InvalidEmail)));
+
+        // Sent, and usable exactly once; the address is matched case-insensitively.
+        assert!(request(&pool, &email.to_uppercase(), Some("203.0.113.5")).await.is_ok());
+        let token = mailed_token(&messages, &email);
+        assert_eq!(consume(&pool, &token).await.unwrap(), Some(email.clone()));
+        assert_eq!(consume(&pool, &token).await.unwrap(), None);
+        assert_eq!(consume(&pool, "not-a-token").await.unwrap(), None);
+
+        // Expired links are refused.
+        assert!(request(&pool, &email, None).await.is_ok());
+        let token = mailed_token(&messages, &email);
+        expire(&pool, &email).await;
+        assert_eq!(consume(&pool, &token).await.unwrap(), None);
+
+        // Two links are on the books; concurrent requests may only add three more.
+        let results = futures_util::future::join_all((0..10).map(|_| request(&pool, &email, None))).await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 3);
+        assert!(results.iter().filter(|r| r.is_err()).all(|r| matches!(r, Err(RequestError::Throttled))));
+        assert_eq!(messages.lock().unwrap().iter().filter(|m| m.contains(&email)).count(), 5);
+
+        sqlx::query!("DELETE FROM public.magic_links WHERE email = $1", email)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
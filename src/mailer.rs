@@ -0,0 +1,74 @@
+//! Outgoing email over SMTP.
+//!
+//! Settings:
+//! - `SMTP_HOST`: required to send mail
+//! - `SMTP_PORT`: default 587 with `starttls`, 465 with `tls`, 25 with `none`
+//! - `SMTP_TLS`: `starttls` (default), `tls`, or `none` for a local development sink
+//! - `SMTP_USERNAME` / `SMTP_PASSWORD`: optional credentials
+//! - `MAIL_FROM`: sender address, required
+
+use std::env;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use once_cell::sync::OnceCell;
+
+static TRANSPORT: OnceCell<AsyncSmtpTransport<Tokio1Executor>> = OnceCell::new();
+
+fn transport() -> Result<&'static AsyncSmtpTransport<Tokio1Executor>, String> {
+    TRANSPORT.get_or_try_init(|| {
+        let host = env::var("SMTP_HOST").map_err(|_| "Missing SMTP_HOST".to_string())?;
+        let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+
+        let mut builder = match tls.as_str() {
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host),
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host),
+            "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)),
+            other => return Err(format!("Unknown SMTP_TLS mode: {}", other)),
+        }
+        .map_err(|e| format!("SMTP setup failed: {}", e))?;
+
+        if let Some(port) = env::var("SMTP_PORT").ok().and_then(|p| p.parse().ok()) {
+            builder = builder.port(port);
+        }
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(builder.build())
+    })
+}
+
+/// Sends all mail to a plain SMTP sink on `port`; the first call wins.
+#[cfg(test)]
+pub fn use_test_transport(port: u16) {
+    let _ = TRANSPORT.set(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1").port(port).build());
+}
+
+#[cfg(not(test))]
+fn sender() -> Result<String, String> {
+    env::var("MAIL_FROM").map_err(|_| "Missing MAIL_FROM".to_string())
+}
+
+#[cfg(test)]
+fn sender() -> Result<String, String> {
+    Ok(env::var("MAIL_FROM").unwrap_or_else(|_| "engine@example.com".to_string()))
+}
+
+/// Sends a plain-text email.
+pub async fn send(to: &str, subject: &str, body: String) -> Result<(), String> {
+    let from: Mailbox = sender()?.parse().map_err(|e| format!("Invalid MAIL_FROM: {}", e))?;
+    let to: Mailbox = to.parse().map_err(|e| format!("Invalid recipient: {}", e))?;
+
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .body(body)
+        .map_err(|e| format!("Message build failed: {}", e))?;
+
+    transport()?
+        .send(message)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("SMTP send failed: {}", e))
+}
@@ -0,0 +1,259 @@
+use std::io::Cursor;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+pub struct CoverArt {
+    pub data: Vec<u8>,
+    pub mime: String,
+}
+
+#[derive(Default)]
+pub struct AudioMeta {
+    pub duration_ms: Option<i64>,
+    /// Average bitrate of the audio payload, in bits per second. Tags and
+    /// embedded artwork are not counted.
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub cover: Option<CoverArt>,
+}
+
+/// Opens `data` with the demuxer matching its container.
+pub fn open(data: Vec<u8>, mime: Option<&str>) -> Result<ProbeResult, String> {
+    let mut hint = Hint::new();
+    if let Some(mime) = mime {
+        hint.mime_type(mime);
+    }
+
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+    symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported audio: {}", e))
+}
+
+/// Reads stream parameters and tags from MP3/ID3, AAC/MP4, Ogg (Vorbis, Opus), FLAC or WAV data.
+pub fn probe(data: Vec<u8>, mime: Option<&str>) -> Result<AudioMeta, String> {
+    let mut probed = open(data, mime)?;
+    let mut meta = AudioMeta::default();
+
+    // Tags in front of the container (ID3v2 on MP3) are picked up by the probe,
+    // tags inside it (Vorbis comments, MP4 atoms) by the format reader.
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        meta.apply(rev);
+    }
+    if let Some(rev) = probed.format.metadata().current() {
+        meta.apply(rev);
+    }
+
+    let track = probed.format.default_track().ok_or("No audio track found")?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    meta.sample_rate = params.sample_rate.map(|r| r as i32);
+    meta.channels = params.channels.map(|c| c.count() as i32);
+
+    let payload = scan_packets(probed.format.as_mut(), track_id);
+    let frames = params.n_frames.or(payload.map(|p| p.frames));
+
+    meta.duration_ms = match (frames, params.time_base, params.sample_rate) {
+        (Some(frames), Some(tb), _) => {
+            let time = tb.calc_time(frames);
+            Some(time.seconds as i64 * 1000 + (time.frac * 1000.0) as i64)
+        }
+        (Some(frames), None, Some(rate)) if rate > 0 => Some((frames * 1000 / rate as u64) as i64),
+        _ => None,
+    };
+
+    if let (Some(ms), Some(payload)) = (meta.duration_ms.filter(|ms| *ms > 0), payload) {
+        meta.bitrate = i32::try_from(payload.bytes * 8 * 1000 / ms as u64).ok();
+    }
+
+    Ok(meta)
+}
+
+#[derive(Clone, Copy)]
+struct Payload {
+    frames: u64,
+    bytes: u64,
+}
+
+/// Sums packet durations and sizes for the track. The durations give the length
+/// of containers that do not declare one up front (e.g. VBR MP3 without a Xing
+/// header), the sizes the bitrate. Packets are demuxed, not decoded.
+fn scan_packets(format: &mut dyn FormatReader, track_id: u32) -> Option<Payload> {
+    let mut payload = Payload { frames: 0, bytes: 0 };
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => {
+                payload.frames += packet.dur;
+                payload.bytes += packet.data.len() as u64;
+            }
+            Ok(_) => {}
+            Err(SymphoniaError::IoError(_)) => break,
+            Err(_) => return None,
+        }
+    }
+    (payload.frames > 0).then_some(payload)
+}
+
+impl AudioMeta {
+    fn apply(&mut self, rev: &MetadataRevision) {
+        for tag in rev.tags() {
+            let value = tag.value.to_string();
+            if value.trim().is_empty() {
+                continue;
+            }
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = Some(value),
+                Some(StandardTagKey::Artist) => self.artist = Some(value),
+                _ => {}
+            }
+        }
+
+        // Prefer the front cover, but take any picture over none.
+        let visual = rev
+            .visuals()
+            .iter()
+            .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+            .or_else(|| rev.visuals().first());
+        if let Some(visual) = visual {
+            self.cover = Some(CoverArt {
+                data: visual.data.to_vec(),
+                mime: visual.media_type.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COVER: [u8; 20_000] = [0x89; 20_000];
+
+    /// MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, stereo: 417 bytes and 1152 samples per frame.
+    fn mp3_frames(count: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+        frame.repeat(count)
+    }
+
+    fn id3_frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    /// An ID3v2.3 tag with a title, an artist and a front cover.
+    fn id3_tag() -> Vec<u8> {
+        let mut apic = b"\0image/png\0\x03\0".to_vec();
+        apic.extend_from_slice(&COVER);
+        let frames = [id3_frame(b"TIT2", b"\0Night Shift"), id3_frame(b"TPE1", b"\0The Hosts"), id3_frame(b"APIC", &apic)]
+            .concat();
+
+        let size = frames.len() as u32;
+        let syncsafe = [(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F];
+        [b"ID3\x03\0\0".as_slice(), &syncsafe, &frames].concat()
+    }
+
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |crc, byte| {
+            (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+        })
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |crc, byte| {
+            (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+                if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 }
+            })
+        })
+    }
+
+    fn flac_block(kind: u8, last: bool, body: &[u8]) -> Vec<u8> {
+        let mut block = vec![kind | if last { 0x80 } else { 0 }];
+        block.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        block.extend_from_slice(body);
+        block
+    }
+
+    /// 16-bit stereo FLAC at 44.1 kHz made of `count` 4096-sample frames of
+    /// constant subframes, with Vorbis comments and a front cover.
+    fn flac(count: u8) -> (Vec<u8>, usize) {
+        let mut streaminfo = vec![0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0];
+        let samples = count as u64 * 4096;
+        let packed = (44_100u64 << 44) | (1 << 41) | (15 << 36) | samples;
+        streaminfo.extend_from_slice(&packed.to_be_bytes());
+        streaminfo.extend_from_slice(&[0; 16]);
+
+        let mut comments = Vec::new();
+        comments.extend_from_slice(&4u32.to_le_bytes());
+        comments.extend_from_slice(b"test");
+        comments.extend_from_slice(&2u32.to_le_bytes());
+        for comment in [&b"TITLE=Night Shift"[..], b"ARTIST=The Hosts"] {
+            comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comments.extend_from_slice(comment);
+        }
+
+        let mut picture = 3u32.to_be_bytes().to_vec();
+        picture.extend_from_slice(&9u32.to_be_bytes());
+        picture.extend_from_slice(b"image/png");
+        picture.extend_from_slice(&[0; 4 * 5]);
+        picture.extend_from_slice(&(COVER.len() as u32).to_be_bytes());
+        picture.extend_from_slice(&COVER);
+
+        let mut data = b"fLaC".to_vec();
+        data.extend(flac_block(0, false, &streaminfo));
+        data.extend(flac_block(4, false, &comments));
+        data.extend(flac_block(6, true, &picture));
+
+        let start = data.len();
+        for number in 0..count {
+            let mut frame = vec![0xFF, 0xF8, 0xC9, 0x18, number];
+            frame.push(crc8(&frame));
+            frame.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0xFF, 0x00]);
+            frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+            data.extend(frame);
+        }
+        let payload = data.len() - start;
+        (data, payload)
+    }
+
+    #[test]
+    fn reads_mp3_tags_without_counting_them_towards_the_bitrate() {
+        let data = [id3_tag(), mp3_frames(40)].concat();
+        let meta = probe(data, Some("audio/mpeg")).unwrap();
+
+        assert_eq!(meta.title.as_deref(), Some("Night Shift"));
+        assert_eq!(meta.artist.as_deref(), Some("The Hosts"));
+        let cover = meta.cover.unwrap();
+        assert_eq!((cover.data.len(), cover.mime.as_str()), (COVER.len(), "image/png"));
+
+        assert_eq!((meta.sample_rate, meta.channels), (Some(44_100), Some(2)));
+        assert_eq!(meta.duration_ms, Some(40 * 1152 * 1000 / 44_100));
+        let bitrate = meta.bitrate.unwrap();
+        assert!((127_000..=129_000).contains(&bitrate), "bitrate {bitrate}");
+    }
+
+    #[test]
+    fn reads_flac_stream_info_and_vorbis_comments() {
+        let (data, payload) = flac(10);
+        let meta = probe(data, Some("audio/flac")).unwrap();
+
+        assert_eq!(meta.title.as_deref(), Some("Night Shift"));
+        assert_eq!(meta.artist.as_deref(), Some("The Hosts"));
+        assert_eq!(meta.cover.map(|c| c.data.len()), Some(COVER.len()));
+
+        assert_eq!((meta.sample_rate, meta.channels), (Some(44_100), Some(2)));
+        let ms = 10 * 4096 * 1000 / 44_100;
+        assert_eq!(meta.duration_ms, Some(ms));
+        assert_eq!(meta.bitrate, Some((payload as i64 * 8 * 1000 / ms) as i32));
+    }
+}
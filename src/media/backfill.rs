@@ -0,0 +1,622 @@
+//! Bulk ingestion of `images` / `audio` rows that were never fetched (`is_indb = 0`).
+//!
+//! A backfill walks the pending rows in batches, ingesting up to `concurrency`
+//! at a time, and keeps its counters in `public.backfills` as it goes. Rows
+//! that fail are recorded in `public.backfill_failures` with the reason, so an
+//! interrupted backfill can be resumed without retrying them and the final
+//! report can group failures by cause.
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::api::{audio_handler, image_handler};
+use crate::media::fetch::MediaKind;
+use crate::media::storage::SharedStorage;
+
+const BATCH: i64 = 100;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const MAX_CONCURRENCY: usize = 32;
+/// A running backfill without progress for this long is assumed dead and may be replaced.
+const STALE_SECONDS: i64 = 900;
+const PROGRESS_EVERY: i32 = 25;
+const EXAMPLES_PER_REASON: i32 = 5;
+
+pub enum StartError {
+    AlreadyRunning,
+    NotResumable,
+    Database(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailureGroup {
+    pub status: i32,
+    pub reason: String,
+    pub count: i64,
+    pub examples: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub status: String,
+    pub kinds: Vec<String>,
+    pub concurrency: i32,
+    pub total: i32,
+    pub succeeded: i32,
+    pub failed: i32,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub failures: Vec<FailureGroup>,
+}
+
+fn parse_kind(label: &str) -> Option<MediaKind> {
+    match label {
+        "image" => Some(MediaKind::Image),
+        "audio" => Some(MediaKind::Audio),
+        _ => None,
+    }
+}
+
+/// Groups errors like "Download failed: error sending request for url (...)" under "Download failed".
+fn reason(error: &str) -> String {
+    error.split(':').next().unwrap_or(error).trim().to_string()
+}
+
+async fn count_pending(pool: &PgPool, id: &str, kind: MediaKind) -> Result<i64, sqlx::Error> {
+    match kind {
+        MediaKind::Image => {
+            sqlx::query_scalar!(
+                r#"
+                SELECT count(*) AS "count!" FROM public.images i
+                WHERE coalesce(i.is_indb, 0) = 0
+                  AND NOT EXISTS (
+                      SELECT 1 FROM public.backfill_failures f
+                      WHERE f.backfill_id = $1 AND f.kind = 'image' AND f.row_id = i.id
+                  )
+                "#,
+                id
+            )
+            .fetch_one(pool)
+            .await
+        }
+        MediaKind::Audio => {
+            sqlx::query_scalar!(
+                r#"
+                SELECT count(*) AS "count!" FROM public.audio a
+                WHERE coalesce(a.is_indb, 0) = 0
+                  AND NOT EXISTS (
+                      SELECT 1 FROM public.backfill_failures f
+                      WHERE f.backfill_id = $1 AND f.kind = 'audio' AND f.row_id = a.id
+                  )
+                "#,
+                id
+            )
+            .fetch_one(pool)
+            .await
+        }
+    }
+}
+
+struct Pending {
+    id: String,
+    url: Option<String>,
+}
+
+async fn next_batch(pool: &PgPool, id: &str, kind: MediaKind) -> Result<Vec<Pending>, sqlx::Error> {
+    match kind {
+        MediaKind::Image => {
+            sqlx::query_as!(
+                Pending,
+                r#"
+                SELECT i.id, i.url FROM public.images i
+                WHERE coalesce(i.is_indb, 0) = 0
+                  AND NOT EXISTS (
+                      SELECT 1 FROM public.backfill_failures f
+                      WHERE f.backfill_id = $1 AND f.kind = 'image' AND f.row_id = i.id
+                  )
+                ORDER BY i.id
+                LIMIT $2
+                "#,
+                id,
+                BATCH
+            )
+            .fetch_all(pool)
+            .await
+        }
+        MediaKind::Audio => {
+            sqlx::query_as!(
+                Pending,
+                r#"
+                SELECT a.id, a.url FROM public.audio a
+                WHERE coalesce(a.is_indb, 0) = 0
+                  AND NOT EXISTS (
+                      SELECT 1 FROM public.backfill_failures f
+                      WHERE f.backfill_id = $1 AND f.kind = 'audio' AND f.row_id = a.id
+                  )
+                ORDER BY a.id
+                LIMIT $2
+                "#,
+                id,
+                BATCH
+            )
+            .fetch_all(pool)
+            .await
+        }
+    }
+}
+
+/// Creates a backfill, or reopens an interrupted one, and marks it running.
+/// Call [`run`] with the returned id to do the work.
+pub async fn start(
+    pool: &PgPool,
+    kinds: &[MediaKind],
+    concurrency: usize,
+    resume: Option<&str>,
+) -> Result<String, StartError> {
+    let db = |e: sqlx::Error| StartError::Database(e.to_string());
+    let now = Utc::now().timestamp();
+    let concurrency = concurrency.clamp(1, MAX_CONCURRENCY) as i32;
+
+    sqlx::query!(
+        r#"
+        UPDATE public.backfills SET status = 'interrupted', updated_at = $1
+        WHERE status = 'running' AND updated_at < $2
+        "#,
+        now,
+        now - STALE_SECONDS,
+    )
+    .execute(pool)
+    .await
+    .map_err(db)?;
+
+    let started = match resume {
+        Some(id) => sqlx::query_scalar!(
+            r#"
+            UPDATE public.backfills
+            SET status = 'running', concurrency = $2, finished_at = NULL, updated_at = $3
+            WHERE id = $1 AND status = 'interrupted'
+            RETURNING id
+            "#,
+            id,
+            concurrency,
+            now,
+        )
+        .fetch_optional(pool)
+        .await,
+        None => sqlx::query_scalar!(
+            r#"
+            INSERT INTO public.backfills (id, status, kinds, concurrency, started_at, updated_at)
+            VALUES ($1, 'running', $2, $3, $4, $4)
+            RETURNING id
+            "#,
+            Uuid::new_v4().to_string(),
+            &kinds.iter().map(|k| k.label().to_string()).collect::<Vec<_>>(),
+            concurrency,
+            now,
+        )
+        .fetch_optional(pool)
+        .await,
+    };
+
+    let id = match started {
+        Ok(Some(id)) => id,
+        Ok(None) => return Err(StartError::NotResumable),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Err(StartError::AlreadyRunning),
+        Err(e) => return Err(db(e)),
+    };
+
+    let stored = sqlx::query_scalar!(
+        r#"
+        SELECT kinds FROM public.backfills WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(db)?;
+
+    let mut pending = 0;
+    for kind in stored.iter().filter_map(|k| parse_kind(k)) {
+        pending += count_pending(pool, &id, kind).await.map_err(db)?;
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE public.backfills SET total = succeeded + failed + $2
+        WHERE id = $1
+        "#,
+        id,
+        pending as i32,
+    )
+    .execute(pool)
+    .await
+    .map_err(db)?;
+
+    Ok(id)
+}
+
+async fn ingest(pool: &PgPool, storage: &SharedStorage, kind: MediaKind, url: Option<&str>) -> Result<(), (StatusCode, String)> {
+    let url = url.ok_or((StatusCode::UNPROCESSABLE_ENTITY, "Row has no URL".to_string()))?;
+    match kind {
+        MediaKind::Image => image_handler::ingest(pool, storage, url).await.map(|_| ()),
+        MediaKind::Audio => audio_handler::ingest(pool, storage, url).await.map(|_| ()),
+    }
+}
+
+async fn record(
+    pool: &PgPool,
+    id: &str,
+    kind: MediaKind,
+    row: &Pending,
+    outcome: Result<(), (StatusCode, String)>,
+) -> Result<(i32, i32), sqlx::Error> {
+    let now = Utc::now().timestamp();
+
+    if let Err((status, error)) = &outcome {
+        sqlx::query!(
+            r#"
+            INSERT INTO public.backfill_failures (backfill_id, kind, row_id, url, status, reason, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT DO NOTHING
+            "#,
+            id,
+            kind.label(),
+            row.id,
+            row.url,
+            status.as_u16() as i32,
+            reason(error),
+            error,
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    let counts = sqlx::query!(
+        r#"
+        UPDATE public.backfills
+        SET succeeded = succeeded + $2, failed = failed + $3, updated_at = $4
+        WHERE id = $1
+        RETURNING succeeded, failed
+        "#,
+        id,
+        outcome.is_ok() as i32,
+        outcome.is_err() as i32,
+        now,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((counts.succeeded, counts.failed))
+}
+
+async fn process(pool: &PgPool, storage: &SharedStorage, id: &str) -> Result<(), String> {
+    let backfill = sqlx::query!(
+        r#"
+        SELECT kinds, concurrency, total FROM public.backfills WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for kind in backfill.kinds.iter().filter_map(|k| parse_kind(k)) {
+        loop {
+            let batch = next_batch(pool, id, kind).await.map_err(|e| e.to_string())?;
+            if batch.is_empty() {
+                break;
+            }
+
+            // Each ingest records its own outcome: recording from this loop
+            // would wait for a connection while the ingests it stopped polling
+            // hold theirs, which deadlocks once concurrency reaches the pool size.
+            let mut results = stream::iter(batch)
+                .map(|row| async move {
+                    let outcome = ingest(pool, storage, kind, row.url.as_deref()).await;
+                    record(pool, id, kind, &row, outcome).await
+                })
+                .buffer_unordered(backfill.concurrency.max(1) as usize);
+
+            while let Some(counts) = results.next().await {
+                let (succeeded, failed) = counts.map_err(|e| e.to_string())?;
+                let done = succeeded + failed;
+                if done % PROGRESS_EVERY == 0 || done == backfill.total {
+                    tracing::info!("Backfill {}: {}/{} done, {} failed", id, done, backfill.total, failed);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Works through every pending row, then marks the backfill completed. On an
+/// error the backfill is marked interrupted so it can be resumed.
+pub async fn run(pool: &PgPool, storage: &SharedStorage, id: &str) -> Result<(), String> {
+    let outcome = process(pool, storage, id).await;
+    let now = Utc::now().timestamp();
+
+    sqlx::query!(
+        r#"
+        UPDATE public.backfills SET status = $2, finished_at = $3, updated_at = $3
+        WHERE id = $1
+        "#,
+        id,
+        if outcome.is_ok() { "completed" } else { "interrupted" },
+        now,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    outcome
+}
+
+pub async fn report(pool: &PgPool, id: &str) -> Result<Option<Report>, String> {
+    let Some(backfill) = sqlx::query!(
+        r#"
+        SELECT id, status, kinds, concurrency, total, succeeded, failed, started_at, finished_at
+        FROM public.backfills
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.to_string())?
+    else {
+        return Ok(None);
+    };
+
+    let groups = sqlx::query!(
+        r#"
+        SELECT status, reason, count(*) AS "count!", (array_agg(coalesce(url, row_id) ORDER BY row_id))[1:$2] AS "examples!"
+        FROM public.backfill_failures
+        WHERE backfill_id = $1
+        GROUP BY status, reason
+        ORDER BY count(*) DESC, status
+        "#,
+        id,
+        EXAMPLES_PER_REASON,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(Some(Report {
+        id: backfill.id,
+        status: backfill.status,
+        kinds: backfill.kinds,
+        concurrency: backfill.concurrency,
+        total: backfill.total,
+        succeeded: backfill.succeeded,
+        failed: backfill.failed,
+        started_at: backfill.started_at,
+        finished_at: backfill.finished_at,
+        failures: groups
+            .into_iter()
+            .map(|g| FailureGroup {
+                status: g.status,
+                reason: g.reason,
+                count: g.count,
+                examples: g.examples,
+            })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::media::blobs;
+    use crate::media::storage::postgres::PostgresStorage;
+    use axum::extract::{Path, State};
+    use axum::routing::get;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Only one backfill runs at a time, and a running one walks every pending
+    /// image, so these tests take turns.
+    static BACKFILLS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    type Hits = Arc<Mutex<HashMap<String, usize>>>;
+
+    /// Serves `/ok-*` as a PNG unique to this origin, `/broken-*` as text and anything else as a 404.
+    async fn origin() -> (String, Hits, Vec<u8>) {
+        let seed = Uuid::new_v4().into_bytes();
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, image::Rgb([seed[0], seed[1], seed[2]])))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let hits = Hits::default();
+
+        let body = png.clone();
+        let app = axum::Router::new()
+            .route(
+                "/{name}",
+                get(move |State(hits): State<Hits>, Path(name): Path<String>| {
+                    let body = body.clone();
+                    async move {
+                        *hits.lock().unwrap().entry(name.clone()).or_default() += 1;
+                        if name.starts_with("ok") {
+                            (StatusCode::OK, body)
+                        } else if name.starts_with("broken") {
+                            (StatusCode::OK, b"not an image".to_vec())
+                        } else {
+                            (StatusCode::NOT_FOUND, Vec::new())
+                        }
+                    }
+                }),
+            )
+            .with_state(hits.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), hits, png)
+    }
+
+    async fn insert_image(pool: &PgPool, id: &str, url: Option<&str>) {
+        sqlx::query!("INSERT INTO public.images (id, url, is_indb) VALUES ($1, $2, 0)", id, url)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn is_stored(pool: &PgPool, id: &str) -> bool {
+        sqlx::query_scalar!("SELECT is_indb FROM public.images WHERE id = $1", id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            == Some(1)
+    }
+
+    async fn clean_up(pool: &PgPool, backfill: &str, prefix: &str, png: &[u8]) {
+        sqlx::query!("DELETE FROM public.backfills WHERE id = $1", backfill)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query!("DELETE FROM public.images WHERE id LIKE $1 || '%'", prefix)
+            .execute(pool)
+            .await
+            .unwrap();
+        let hash = crate::media::fetch::content_hash(png);
+        let mut conn = pool.acquire().await.unwrap();
+        if let Some(backend) = blobs::release(&mut conn, &hash).await.unwrap() {
+            blobs::purge(pool, &hash, &backend).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn groups_errors_by_their_leading_reason() {
+        assert_eq!(reason("Download failed: error sending request for url (x)"), "Download failed");
+        assert_eq!(reason("Row has no URL"), "Row has no URL");
+    }
+
+    #[tokio::test]
+    async fn walks_every_batch_and_groups_failures_by_reason() {
+        let _turn = BACKFILLS.lock().await;
+        let pool = test_pool().await;
+        let storage: SharedStorage = Arc::new(PostgresStorage::new(pool.clone()));
+        let (base, hits, png) = origin().await;
+        let prefix = format!("backfill-{}-", Uuid::new_v4().simple());
+
+        // More missing images than fit in one batch, so the walk has to go on
+        // past the first batch even though none of it succeeded.
+        let missing = BATCH as usize + 5;
+        for n in 0..missing {
+            insert_image(&pool, &format!("{}a{:03}", prefix, n), Some(&format!("{}/missing-{}", base, n))).await;
+        }
+        for n in 0..3 {
+            insert_image(&pool, &format!("{}b{}", prefix, n), Some(&format!("{}/ok-{}", base, n))).await;
+        }
+        for n in 0..2 {
+            insert_image(&pool, &format!("{}c{}", prefix, n), Some(&format!("{}/broken-{}", base, n))).await;
+        }
+        insert_image(&pool, &format!("{}d", prefix), None).await;
+
+        let id = start(&pool, &[MediaKind::Image], 8, None).await.ok().unwrap();
+        run(&pool, &storage, &id).await.unwrap();
+
+        let report = report(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(report.status, "completed");
+        assert_eq!((report.total, report.succeeded, report.failed), (missing as i32 + 6, 3, missing as i32 + 3));
+        assert!(report.finished_at.is_some());
+        assert!(is_stored(&pool, &format!("{}b0", prefix)).await);
+        // Every row was tried exactly once.
+        assert!(hits.lock().unwrap().values().all(|&n| n == 1));
+        assert_eq!(hits.lock().unwrap().len(), missing + 5);
+
+        let groups: Vec<_> = report.failures.iter().map(|g| (g.status, g.reason.as_str(), g.count)).collect();
+        assert_eq!(
+            groups,
+            vec![
+                (404, "Origin has no image at this URL", missing as i64),
+                (422, "Payload is not a recognised image format", 2),
+                (422, "Row has no URL", 1),
+            ]
+        );
+        assert_eq!(
+            report.failures[0].examples,
+            (0..EXAMPLES_PER_REASON).map(|n| format!("{}/missing-{}", base, n)).collect::<Vec<_>>()
+        );
+        assert_eq!(report.failures[2].examples, vec![format!("{}d", prefix)]);
+
+        clean_up(&pool, &id, &prefix, &png).await;
+    }
+
+    #[tokio::test]
+    async fn resumes_without_retrying_failed_rows() {
+        let _turn = BACKFILLS.lock().await;
+        let pool = test_pool().await;
+        let storage: SharedStorage = Arc::new(PostgresStorage::new(pool.clone()));
+        let (base, hits, png) = origin().await;
+        let prefix = format!("backfill-{}-", Uuid::new_v4().simple());
+
+        insert_image(&pool, &format!("{}gone", prefix), Some(&format!("{}/gone", base))).await;
+        let id = start(&pool, &[MediaKind::Image], 2, None).await.ok().unwrap();
+        run(&pool, &storage, &id).await.unwrap();
+
+        // Pretend the backfill died, and that more rows turned up meanwhile.
+        sqlx::query!("UPDATE public.backfills SET status = 'interrupted' WHERE id = $1", id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        insert_image(&pool, &format!("{}new", prefix), Some(&format!("{}/ok-new", base))).await;
+
+        assert_eq!(start(&pool, &[MediaKind::Image], 2, Some(&id)).await.ok().as_deref(), Some(id.as_str()));
+        run(&pool, &storage, &id).await.unwrap();
+
+        let report = report(&pool, &id).await.unwrap().unwrap();
+        assert_eq!((report.status.as_str(), report.total, report.succeeded, report.failed), ("completed", 2, 1, 1));
+        assert_eq!(hits.lock().unwrap().get("gone"), Some(&1));
+        assert!(!is_stored(&pool, &format!("{}gone", prefix)).await);
+        assert!(is_stored(&pool, &format!("{}new", prefix)).await);
+
+        // Only interrupted backfills can be resumed.
+        assert!(matches!(start(&pool, &[MediaKind::Image], 2, Some(&id)).await, Err(StartError::NotResumable)));
+
+        clean_up(&pool, &id, &prefix, &png).await;
+    }
+
+    #[tokio::test]
+    async fn replaces_a_running_backfill_only_once_it_goes_stale() {
+        let _turn = BACKFILLS.lock().await;
+        let pool = test_pool().await;
+        let now = Utc::now().timestamp();
+        let stuck = Uuid::new_v4().to_string();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO public.backfills (id, status, kinds, concurrency, started_at, updated_at)
+            VALUES ($1, 'running', '{image}', 1, $2, $2)
+            "#,
+            stuck,
+            now,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(matches!(start(&pool, &[MediaKind::Image], 1, None).await, Err(StartError::AlreadyRunning)));
+
+        sqlx::query!("UPDATE public.backfills SET updated_at = $2 WHERE id = $1", stuck, now - STALE_SECONDS - 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let replacement = start(&pool, &[MediaKind::Image], 1, None).await.ok().unwrap();
+        let status = sqlx::query_scalar!("SELECT status FROM public.backfills WHERE id = $1", stuck)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "interrupted");
+
+        sqlx::query!("DELETE FROM public.backfills WHERE id = ANY($1)", &[stuck, replacement][..])
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+use chrono::Utc;
+use sqlx::{PgConnection, PgPool};
+use super::storage::{self, MediaStorage};
+
+/// Uploads recorded longer ago than this without an index entry belong to an
+/// ingest that rolled back.
+const STALE_UPLOAD_SECONDS: i64 = 24 * 60 * 60;
+
+/// How often an ingest stores its blobs and writes the rows referencing them
+/// before giving up on garbage collection removing the blobs in between.
+pub const STORE_ATTEMPTS: u32 = 3;
+
+/// Writes `data` to `storage` and records it in the blob index, doing nothing
+/// if a blob with this hash is already stored.
+pub async fn store(
+    pool: &PgPool,
+    conn: &mut PgConnection,
+    storage: &dyn MediaStorage,
+    hash: &str,
+    data: &[u8],
+    mime: Option<&str>,
+) -> Result<(), String> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT hash FROM public.media_blobs
+        WHERE hash = $1
+        "#,
+        hash
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| format!("Blob lookup failed: {}", e))?;
+
+    if exists.is_some() {
+        return Ok(());
+    }
+
+    // A payload outside the database outlives a rollback of `conn`, so note the
+    // upload on its own first; garbage collection purges it if the index entry
+    // never commits.
+    if !storage.transactional() {
+        sqlx::query!(
+            r#"
+            INSERT INTO public.media_blob_uploads (hash, storage, started_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (hash, storage) DO UPDATE
+            SET started_at = EXCLUDED.started_at
+            "#,
+            hash,
+            storage.name(),
+            Utc::now().timestamp(),
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Upload record failed: {}", e))?;
+    }
+
+    storage.put_in(&mut *conn, hash, data).await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.media_blobs (hash, size, mime, storage, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (hash) DO NOTHING
+        "#,
+        hash,
+        data.len() as i64,
+        mime,
+        storage.name(),
+        Utc::now().timestamp(),
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| format!("Blob index insert failed: {}", e))?;
+
+    Ok(())
+}
+
+/// Reads a blob from whichever backend the index says it lives in.
+pub async fn load(pool: &PgPool, hash: &str) -> Result<Option<Vec<u8>>, String> {
+    let backend = sqlx::query_scalar!(
+        r#"
+        SELECT storage FROM public.media_blobs
+        WHERE hash = $1
+        "#,
+        hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Blob lookup failed: {}", e))?;
+
+    match backend {
+        Some(name) => storage::open(&name, pool)?.get(hash).await,
+        None => Ok(None),
+    }
+}
+
+/// Reads bytes `start..=end` of a blob without loading the rest of it.
+pub async fn load_range(pool: &PgPool, hash: &str, start: u64, end: u64) -> Result<Option<Vec<u8>>, String> {
+    let backend = sqlx::query_scalar!(
+        r#"
+        SELECT storage FROM public.media_blobs
+        WHERE hash = $1
+        "#,
+        hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Blob lookup failed: {}", e))?;
+
+    match backend {
+        Some(name) => storage::open(&name, pool)?.get_range(hash, start, end).await,
+        None => Ok(None),
+    }
+}
+
+/// Drops the index entry if no image or audio row references it any more. Payloads
+/// kept in the database go with it; for other backends, returns the one the payload
+/// should be purged from once committed.
+pub async fn release(conn: &mut PgConnection, hash: &str) -> Result<Option<String>, String> {
+    sqlx::query_scalar!(
+        r#"
+        WITH released AS (
+            DELETE FROM public.media_blobs b
+            WHERE b.hash = $1
+              AND NOT EXISTS (SELECT 1 FROM public.images i WHERE i.content_hash = b.hash)
+              AND NOT EXISTS (SELECT 1 FROM public.audio a WHERE a.content_hash = b.hash OR a.cover_hash = b.hash)
+              AND NOT EXISTS (SELECT 1 FROM public.image_variants v WHERE v.content_hash = b.hash)
+            RETURNING b.hash, b.storage
+        ), payload AS (
+            DELETE FROM public.media_blob_data d
+            USING released r
+            WHERE d.hash = r.hash AND r.storage = 'postgres'
+        )
+        SELECT storage AS "storage!" FROM released
+        WHERE storage <> 'postgres'
+        "#,
+        hash
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| format!("Blob release failed: {}", e))
+}
+
+/// Removes a released blob's payload from its backend, unless it has been stored
+/// there again since.
+pub async fn purge(pool: &PgPool, hash: &str, backend: &str) -> Result<(), String> {
+    let stored = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM public.media_blobs WHERE hash = $1 AND storage = $2) AS "stored!"
+        "#,
+        hash,
+        backend
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("Blob lookup failed: {}", e))?;
+
+    if stored {
+        return Ok(());
+    }
+    storage::open(backend, pool)?.delete(hash).await
+}
+
+/// Whether `e` comes from writing a reference to a blob that garbage collection
+/// removed after [`store`] found it in the index. Storing it again fixes that.
+pub fn collected(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.is_foreign_key_violation())
+}
+
+/// Purges payloads uploaded before `before` that never made it into the index,
+/// returning how many, and forgets the uploads that did.
+async fn sweep_uploads(pool: &PgPool, before: i64) -> Result<u64, String> {
+    let uploads = sqlx::query!(
+        r#"
+        SELECT u.hash, u.storage, u.started_at,
+            EXISTS (SELECT 1 FROM public.media_blobs b WHERE b.hash = u.hash AND b.storage = u.storage) AS "indexed!"
+        FROM public.media_blob_uploads u
+        WHERE u.started_at < $1
+        "#,
+        before
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Upload sweep failed: {}", e))?;
+
+    let mut purged = 0;
+    for upload in &uploads {
+        if !upload.indexed {
+            purge(pool, &upload.hash, &upload.storage).await?;
+            purged += 1;
+        }
+        // Leave the record alone if the same payload is being uploaded again.
+        sqlx::query!(
+            r#"
+            DELETE FROM public.media_blob_uploads
+            WHERE hash = $1 AND storage = $2 AND started_at = $3
+            "#,
+            upload.hash,
+            upload.storage,
+            upload.started_at,
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Upload sweep failed: {}", e))?;
+    }
+
+    Ok(purged)
+}
+
+/// Drops the index entries nothing references any more, all of them or only those
+/// for `hashes`, and purges their payloads, returning how many were removed. Payloads
+/// kept in the database go in the same statement, so an ingest that stores one again
+/// right after cannot lose it to a late purge.
+async fn release_unreferenced(pool: &PgPool, hashes: Option<&[String]>) -> Result<u64, String> {
+    let released = sqlx::query!(
+        r#"
+        WITH released AS (
+            DELETE FROM public.media_blobs b
+            WHERE ($1::text[] IS NULL OR b.hash = ANY($1))
+              AND NOT EXISTS (SELECT 1 FROM public.images i WHERE i.content_hash = b.hash)
+              AND NOT EXISTS (SELECT 1 FROM public.audio a WHERE a.content_hash = b.hash OR a.cover_hash = b.hash)
+              AND NOT EXISTS (SELECT 1 FROM public.image_variants v WHERE v.content_hash = b.hash)
+            RETURNING b.hash, b.storage
+        ), payload AS (
+            DELETE FROM public.media_blob_data d
+            USING released r
+            WHERE d.hash = r.hash AND r.storage = 'postgres'
+        )
+        SELECT hash AS "hash!", storage AS "storage!" FROM released
+        "#,
+        hashes
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Garbage collection failed: {}", e))?;
+
+    for blob in released.iter().filter(|b| b.storage != "postgres") {
+        purge(pool, &blob.hash, &blob.storage).await?;
+    }
+
+    Ok(released.len() as u64)
+}
+
+/// Deletes every blob that is no longer referenced, returning how many were removed.
+/// Image variants rendered from a previous version of their source are dropped first,
+/// and payloads left behind by rolled-back uploads are purged last.
+pub async fn collect_garbage(pool: &PgPool) -> Result<u64, String> {
+    sqlx::query!(
+        r#"
+        DELETE FROM public.image_variants v
+        USING public.images i
+        WHERE i.id = v.image_id
+          AND i.content_hash IS DISTINCT FROM v.source_hash
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Stale variant cleanup failed: {}", e))?;
+
+    let released = release_unreferenced(pool, None).await?;
+    let orphaned = sweep_uploads(pool, Utc::now().timestamp() - STALE_UPLOAD_SECONDS).await?;
+
+    Ok(released + orphaned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::image_handler;
+    use crate::db::test_pool;
+    use crate::media::storage::fs::FsStorage;
+    use crate::media::storage::postgres::PostgresStorage;
+    use crate::media::storage::SharedStorage;
+    use axum::routing::get;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn random_hash() -> String {
+        format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+    }
+
+    async fn recorded_upload(pool: &PgPool, hash: &str) -> Option<String> {
+        sqlx::query_scalar!("SELECT storage FROM public.media_blob_uploads WHERE hash = $1", hash)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn postgres_payload_rolls_back_with_its_index_entry() {
+        let pool = test_pool().await;
+        let storage = PostgresStorage::new(pool.clone());
+        let hash = random_hash();
+
+        let mut tx = pool.begin().await.unwrap();
+        store(&pool, &mut tx, &storage, &hash, b"payload", None).await.unwrap();
+        tx.rollback().await.unwrap();
+
+        let data = sqlx::query_scalar!("SELECT hash FROM public.media_blob_data WHERE hash = $1", hash)
+            .fetch_optional(&pool)
+            .await
+            .unwrap();
+        assert!(data.is_none());
+
+        let mut tx = pool.begin().await.unwrap();
+        store(&pool, &mut tx, &storage, &hash, b"payload", None).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(load(&pool, &hash).await.unwrap().as_deref(), Some(&b"payload"[..]));
+        assert_eq!(load_range(&pool, &hash, 1, 3).await.unwrap().as_deref(), Some(&b"ayl"[..]));
+        assert_eq!(load_range(&pool, &hash, 5, 99).await.unwrap().as_deref(), Some(&b"ad"[..]));
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(release(&mut conn, &hash).await.unwrap(), None);
+        assert!(storage.get(&hash).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn records_uploads_that_outlive_a_rollback() {
+        let pool = test_pool().await;
+        let root = std::env::temp_dir().join(format!("engine-blobs-{}", Uuid::new_v4()));
+        let storage = FsStorage::new(&root);
+        let hash = random_hash();
+
+        let mut tx = pool.begin().await.unwrap();
+        store(&pool, &mut tx, &storage, &hash, b"payload", None).await.unwrap();
+        tx.rollback().await.unwrap();
+
+        assert!(storage.get(&hash).await.unwrap().is_some());
+        assert_eq!(recorded_upload(&pool, &hash).await.as_deref(), Some("fs"));
+
+        sqlx::query!("DELETE FROM public.media_blob_uploads WHERE hash = $1", hash)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn sweeps_stale_uploads_that_never_reached_the_index() {
+        let pool = test_pool().await;
+        let storage = PostgresStorage::new(pool.clone());
+        let (orphan, kept) = (random_hash(), random_hash());
+
+        storage.put(&orphan, b"orphan").await.unwrap();
+        let mut tx = pool.begin().await.unwrap();
+        store(&pool, &mut tx, &storage, &kept, b"kept", None).await.unwrap();
+        tx.commit().await.unwrap();
+        for hash in [&orphan, &kept] {
+            sqlx::query!(
+                "INSERT INTO public.media_blob_uploads (hash, storage, started_at) VALUES ($1, 'postgres', 1000)",
+                hash
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(sweep_uploads(&pool, 2000).await.unwrap(), 1);
+        assert!(storage.get(&orphan).await.unwrap().is_none());
+        assert!(storage.get(&kept).await.unwrap().is_some());
+        assert!(recorded_upload(&pool, &orphan).await.is_none());
+        assert!(recorded_upload(&pool, &kept).await.is_none());
+
+        let mut conn = pool.acquire().await.unwrap();
+        release(&mut conn, &kept).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ingest_stores_a_blob_again_when_collected_under_it() {
+        let pool = test_pool().await;
+        let storage: SharedStorage = Arc::new(PostgresStorage::new(pool.clone()));
+
+        let seed = Uuid::new_v4().into_bytes();
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, image::Rgb([seed[0], seed[1], seed[2]])))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let app = axum::Router::new().route("/{name}", get(move || async move { png.clone() }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = format!("http://{}/{}", listener.local_addr().unwrap(), Uuid::new_v4());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let (first, second) = (format!("{}-first", origin), format!("{}-second", origin));
+
+        // Leave the blob indexed but unreferenced, as after its last image was replaced.
+        let hash = image_handler::ingest(&pool, &storage, &first).await.unwrap().content_hash.unwrap();
+        sqlx::query!("DELETE FROM public.images WHERE url = $1", first)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let original = load(&pool, &hash).await.unwrap().unwrap();
+
+        // Hold the second URL so its ingest stops at the upsert, after `store` has
+        // found the blob in the index.
+        let blocker = test_pool().await;
+        let mut hold = blocker.begin().await.unwrap();
+        let holder = sqlx::query_scalar!(r#"SELECT pg_backend_pid() AS "pid!""#)
+            .fetch_one(&mut *hold)
+            .await
+            .unwrap();
+        sqlx::query!("INSERT INTO public.images (id, url) VALUES ($1, $2)", Uuid::new_v4().to_string(), second)
+            .execute(&mut *hold)
+            .await
+            .unwrap();
+
+        let ingest = tokio::spawn({
+            let (pool, storage, second) = (pool.clone(), storage.clone(), second.clone());
+            async move { image_handler::ingest(&pool, &storage, &second).await }
+        });
+        let mut waits = 0;
+        while !sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM pg_stat_activity WHERE $1 = ANY(pg_blocking_pids(pid))) AS "blocked!""#,
+            holder
+        )
+        .fetch_one(&blocker)
+        .await
+        .unwrap()
+        {
+            waits += 1;
+            assert!(waits < 200, "ingest never reached the upsert");
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+
+        assert_eq!(release_unreferenced(&pool, Some(std::slice::from_ref(&hash))).await.unwrap(), 1);
+        assert!(load(&pool, &hash).await.unwrap().is_none());
+        hold.rollback().await.unwrap();
+
+        let image = ingest.await.unwrap().unwrap();
+        assert_eq!(image.content_hash.as_deref(), Some(hash.as_str()));
+        assert!(image.created);
+        assert_eq!(load(&pool, &hash).await.unwrap(), Some(original));
+
+        sqlx::query!("DELETE FROM public.images WHERE url = $1", second)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        release(&mut conn, &hash).await.unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Cue {
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Chapter {
+    pub start_ms: i64,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub img: Option<String>,
+}
+
+/// Parses `HH:MM:SS.mmm`, `MM:SS.mmm` or (SRT) `HH:MM:SS,mmm` into milliseconds.
+fn parse_timestamp(value: &str) -> Result<i64, String> {
+    let bad = || format!("Invalid timestamp: {}", value);
+    let (clock, millis) = value.trim().split_once(['.', ',']).ok_or_else(bad)?;
+    let digits = |p: &str| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit());
+    if millis.len() != 3 || !digits(millis) {
+        return Err(bad());
+    }
+    let millis: i64 = millis.parse().map_err(|_| bad())?;
+
+    let parts = clock
+        .split(':')
+        .map(|p| if digits(p) { p.parse::<i64>().map_err(|_| bad()) } else { Err(bad()) })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (h, m, s) = match parts[..] {
+        [h, m, s] => (h, m, s),
+        [m, s] => (0, m, s),
+        _ => return Err(bad()),
+    };
+    if m >= 60 || s >= 60 {
+        return Err(bad());
+    }
+    Ok(((h * 60 + m) * 60 + s) * 1000 + millis)
+}
+
+fn format_timestamp(ms: i64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+/// Splits a caption file into blocks separated by blank lines.
+fn blocks(input: &str) -> Vec<Vec<&str>> {
+    let mut blocks = Vec::new();
+    let mut current = Vec::new();
+    for line in input.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if !current.is_empty() {
+                blocks.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        blocks.push(current);
+    }
+    blocks
+}
+
+fn parse_timing(line: &str) -> Result<(i64, i64), String> {
+    let (start, rest) = line
+        .split_once("-->")
+        .ok_or_else(|| format!("Expected a timing line, got: {}", line))?;
+    // WebVTT allows cue settings after the end time.
+    let end = rest.split_whitespace().next().unwrap_or_default();
+    let (start, end) = (parse_timestamp(start)?, parse_timestamp(end)?);
+    if end < start {
+        return Err(format!("Cue ends before it starts: {}", line));
+    }
+    Ok((start, end))
+}
+
+/// Shared by both formats: every block is an optional identifier line, a
+/// timing line and one or more text lines.
+fn parse_cues(blocks: &[Vec<&str>]) -> Result<Vec<Cue>, String> {
+    let mut cues = Vec::new();
+    for block in blocks {
+        let timing_at = block
+            .iter()
+            .position(|l| l.contains("-->"))
+            .ok_or_else(|| format!("Cue without timing: {}", block.join(" / ")))?;
+        if timing_at > 1 {
+            return Err(format!("Unexpected text before timing: {}", block[0]));
+        }
+        let (start_ms, end_ms) = parse_timing(block[timing_at])?;
+        let text = block[timing_at + 1..].join("\n");
+        if text.trim().is_empty() {
+            continue;
+        }
+        cues.push(Cue { start_ms, end_ms, text });
+    }
+
+    cues.sort_by_key(|c| (c.start_ms, c.end_ms));
+    if cues.is_empty() {
+        return Err("No cues found".into());
+    }
+    Ok(cues)
+}
+
+pub fn parse_vtt(input: &str) -> Result<Vec<Cue>, String> {
+    let input = input.trim_start_matches('\u{feff}');
+    let all = blocks(input);
+    let header = all.first().ok_or("Empty WebVTT file")?;
+    if !header[0].starts_with("WEBVTT") {
+        return Err("WebVTT files must start with WEBVTT".into());
+    }
+
+    // Header, NOTE, STYLE and REGION blocks carry no cues.
+    let body: Vec<_> = all[1..]
+        .iter()
+        .filter(|b| !["NOTE", "STYLE", "REGION"].iter().any(|k| b[0].starts_with(k)))
+        .cloned()
+        .collect();
+    parse_cues(&body)
+}
+
+pub fn parse_srt(input: &str) -> Result<Vec<Cue>, String> {
+    parse_cues(&blocks(input.trim_start_matches('\u{feff}')))
+}
+
+pub fn to_vtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n");
+    for cue in cues {
+        out.push_str(&format!(
+            "\n{} --> {}\n{}\n",
+            format_timestamp(cue.start_ms, '.'),
+            format_timestamp(cue.end_ms, '.'),
+            cue.text
+        ));
+    }
+    out
+}
+
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_timestamp(cue.start_ms, ','),
+            format_timestamp(cue.end_ms, ','),
+            cue.text
+        ));
+    }
+    out
+}
+
+/// Plain text of a transcript, used for full-text search.
+pub fn plain_text(cues: &[Cue]) -> String {
+    cues.iter().map(|c| c.text.replace('\n', " ")).collect::<Vec<_>>().join(" ")
+}
+
+/// Reads WebVTT chapter cues: the cue text is the chapter title.
+pub fn chapters_from_vtt(input: &str) -> Result<Vec<Chapter>, String> {
+    Ok(parse_vtt(input)?
+        .into_iter()
+        .map(|c| Chapter { start_ms: c.start_ms, title: c.text.replace('\n', " "), url: None, img: None })
+        .collect())
+}
+
+/// Checks chapters are non-empty, titled, in order and inside the audio.
+pub fn normalize_chapters(mut chapters: Vec<Chapter>, duration_ms: Option<i64>) -> Result<Vec<Chapter>, String> {
+    if chapters.is_empty() {
+        return Err("No chapters given".into());
+    }
+    chapters.sort_by_key(|c| c.start_ms);
+    for chapter in &mut chapters {
+        chapter.title = chapter.title.trim().to_string();
+        if chapter.title.is_empty() {
+            return Err("Every chapter needs a title".into());
+        }
+        if chapter.start_ms < 0 || duration_ms.is_some_and(|d| chapter.start_ms >= d) {
+            return Err(format!("Chapter \"{}\" starts outside the audio", chapter.title));
+        }
+    }
+    if chapters.windows(2).any(|w| w[0].start_ms == w[1].start_ms) {
+        return Err("Two chapters start at the same time".into());
+    }
+    Ok(chapters)
+}
+
+/// WebVTT chapter track; each chapter runs until the next one (or the end).
+pub fn chapters_to_vtt(chapters: &[Chapter], duration_ms: Option<i64>) -> String {
+    let cues: Vec<Cue> = chapters
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Cue {
+            start_ms: c.start_ms,
+            end_ms: chapters
+                .get(i + 1)
+                .map(|next| next.start_ms)
+                .or(duration_ms)
+                .unwrap_or(c.start_ms),
+            text: c.title.clone(),
+        })
+        .collect();
+    to_vtt(&cues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vtt_and_srt_timestamps() {
+        assert_eq!(parse_timestamp("00:00:01.500"), Ok(1_500));
+        assert_eq!(parse_timestamp("01:02:03.004"), Ok(3_723_004));
+        assert_eq!(parse_timestamp("02:03.004"), Ok(123_004));
+        assert_eq!(parse_timestamp("01:02:03,004"), Ok(3_723_004));
+        assert_eq!(parse_timestamp(" 00:00:00.000 "), Ok(0));
+        // WebVTT hours may exceed two digits.
+        assert_eq!(parse_timestamp("100:00:00.000"), Ok(360_000_000));
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        for value in [
+            "",
+            "00:00:01",
+            "00:00:01.5",
+            "00:00:01.5000",
+            "00:60:00.000",
+            "00:00:60.000",
+            "1:00:00:00.000",
+            "00:00:01.-12",
+            "00:-1:01.000",
+            "+1:00:01.000",
+            "00::01.000",
+            "aa:bb:cc.ddd",
+        ] {
+            assert!(parse_timestamp(value).is_err(), "{:?} should be rejected", value);
+        }
+    }
+
+    #[test]
+    fn formats_timestamps_for_both_formats() {
+        assert_eq!(format_timestamp(3_723_004, '.'), "01:02:03.004");
+        assert_eq!(format_timestamp(3_723_004, ','), "01:02:03,004");
+        assert_eq!(format_timestamp(0, '.'), "00:00:00.000");
+    }
+
+    #[test]
+    fn parses_timing_lines_with_cue_settings() {
+        assert_eq!(parse_timing("00:01.000 --> 00:02.500 align:start line:0"), Ok((1_000, 2_500)));
+        assert!(parse_timing("00:02.000 --> 00:01.000").is_err());
+        assert!(parse_timing("00:01.000 00:02.000").is_err());
+    }
+
+    #[test]
+    fn round_trips_vtt_and_srt() {
+        let vtt = "\u{feff}WEBVTT\n\nNOTE skipped\n\n1\n00:00:01.000 --> 00:00:02.000\nHello\nthere\n\n00:00:03.000 --> 00:00:04.000\nBye\n";
+        let cues = parse_vtt(vtt).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].text, "Hello\nthere");
+
+        assert_eq!(parse_srt(&to_srt(&cues)).unwrap(), cues);
+        assert_eq!(parse_vtt(&to_vtt(&cues)).unwrap(), cues);
+        assert!(parse_vtt("1\n00:00:01.000 --> 00:00:02.000\nHello").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Downloads of remote media for ingestion.
+//!
+//! Settings:
+//! - `MEDIA_FETCH_TIMEOUT_SECONDS`: limit on a whole download, default 300
+//! - `MEDIA_FETCH_MAX_BYTES`: largest payload accepted, default 536870912 (512 MiB)
+
+use std::env;
+use std::time::Duration;
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shared so connections are pooled; the timeouts keep a hung origin from
+/// holding a job worker past `JOB_TIMEOUT_SECONDS`.
+static CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(Duration::from_secs(setting("MEDIA_FETCH_TIMEOUT_SECONDS", 300)))
+        .build()
+        .expect("HTTP client setup failed")
+});
+
+fn setting(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Audio,
+}
+
+impl MediaKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Audio => "audio",
+        }
+    }
+
+    fn accepts(self, bytes: &[u8]) -> bool {
+        match self {
+            MediaKind::Image => infer::is_image(bytes),
+            MediaKind::Audio => infer::is_audio(bytes) || infer::is_video(bytes),
+        }
+    }
+}
+
+pub struct Fetched {
+    pub bytes: Bytes,
+    pub hash: String,
+    pub mime: Option<String>,
+}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Checks that `url` is something [`fetch`] can download.
+pub fn validate_url(url: &str) -> Result<reqwest::Url, (StatusCode, String)> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid URL: {}", e)))?;
+
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Unsupported URL scheme: {}", parsed.scheme())));
+    }
+    Ok(parsed)
+}
+
+/// Downloads `url` and checks that the payload looks like `kind`.
+///
+/// A 404/410 from the origin maps to `404`, a malformed URL or a payload of
+/// the wrong type to `422`, a payload over `MEDIA_FETCH_MAX_BYTES` to `413`,
+/// and any other origin failure (including a timeout) to `502`.
+pub async fn fetch(url: &str, kind: MediaKind) -> Result<Fetched, (StatusCode, String)> {
+    fetch_with(&CLIENT, url, kind, setting("MEDIA_FETCH_MAX_BYTES", 512 * 1024 * 1024)).await
+}
+
+async fn fetch_with(client: &Client, url: &str, kind: MediaKind, max_bytes: u64) -> Result<Fetched, (StatusCode, String)> {
+    let parsed = validate_url(url)?;
+
+    let mut resp = client
+        .get(parsed)
+        .send()
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Download failed: {}", e)))?;
+
+    let status = resp.status();
+    if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE {
+        return Err((StatusCode::NOT_FOUND, format!("Origin has no {} at this URL: HTTP {}", kind.label(), status)));
+    }
+    if !status.is_success() {
+        return Err((StatusCode::BAD_GATEWAY, format!("Failed to fetch {}: HTTP {}", kind.label(), status)));
+    }
+
+    let too_large = || (StatusCode::PAYLOAD_TOO_LARGE, format!("The {} is larger than {} bytes", kind.label(), max_bytes));
+    if resp.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(too_large());
+    }
+
+    // Content-Length may be missing or wrong, so the limit is enforced while reading.
+    let mut body = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Read bytes failed: {}", e)))?
+    {
+        if (body.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let bytes = Bytes::from(body);
+
+    if bytes.is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Origin returned an empty body".into()));
+    }
+    if !kind.accepts(&bytes) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Payload is not a recognised {} format", kind.label())));
+    }
+
+    Ok(Fetched {
+        hash: content_hash(&bytes),
+        mime: infer::get(&bytes).map(|t| t.mime_type().to_string()),
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use futures_util::stream;
+
+    // Smallest well-formed GIF header `infer` recognises.
+    const GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x00\x00\x00;";
+
+    async fn origin() -> String {
+        let app = axum::Router::new()
+            .route("/ok.gif", get(|| async { GIF }))
+            .route("/text", get(|| async { "not an image" }))
+            .route("/gone", get(|| async { StatusCode::GONE }))
+            .route("/broken", get(|| async { StatusCode::INTERNAL_SERVER_ERROR }))
+            .route("/large", get(|| async { vec![0u8; 4096] }))
+            .route(
+                // Chunked, so there is no Content-Length to check up front.
+                "/streamed",
+                get(|| async {
+                    let chunks = (0..8).map(|_| Ok::<_, std::io::Error>(vec![0u8; 1024]));
+                    Body::from_stream(stream::iter(chunks))
+                }),
+            )
+            .route(
+                "/hang",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    GIF
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn client() -> Client {
+        Client::builder().timeout(Duration::from_millis(500)).build().unwrap()
+    }
+
+    async fn status(url: &str, max_bytes: u64) -> StatusCode {
+        fetch_with(&client(), url, MediaKind::Image, max_bytes).await.err().map(|e| e.0).unwrap_or(StatusCode::OK)
+    }
+
+    #[tokio::test]
+    async fn downloads_and_hashes_media() {
+        let base = origin().await;
+        let fetched = fetch_with(&client(), &format!("{}/ok.gif", base), MediaKind::Image, 1024).await.unwrap();
+        assert_eq!(&fetched.bytes[..], GIF);
+        assert_eq!(fetched.hash, content_hash(GIF));
+        assert_eq!(fetched.mime.as_deref(), Some("image/gif"));
+    }
+
+    #[tokio::test]
+    async fn maps_origin_failures() {
+        let base = origin().await;
+        assert_eq!(status(&format!("{}/gone", base), 1024).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(&format!("{}/broken", base), 1024).await, StatusCode::BAD_GATEWAY);
+        assert_eq!(status(&format!("{}/text", base), 1024).await, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(status("ftp://example.com/a.gif", 1024).await, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn enforces_the_size_limit() {
+        let base = origin().await;
+        assert_eq!(status(&format!("{}/large", base), 1024).await, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(status(&format!("{}/streamed", base), 4096).await, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_hung_origin() {
+        let base = origin().await;
+        let started = std::time::Instant::now();
+        assert_eq!(status(&format!("{}/hang", base), 1024).await, StatusCode::BAD_GATEWAY);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
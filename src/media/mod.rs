@@ -0,0 +1,10 @@
+pub mod audio_meta;
+pub mod backfill;
+pub mod blobs;
+pub mod captions;
+pub mod fetch;
+pub mod placeholder;
+pub mod sanitize;
+pub mod storage;
+pub mod variants;
+pub mod waveform;
@@ -0,0 +1,48 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::io::Cursor;
+
+const BLURHASH_SIZE: u32 = 32;
+const LQIP_SIZE: u32 = 16;
+
+pub struct Placeholder {
+    pub blurhash: String,
+    /// A tiny image as a `data:` URI, small enough to inline into HTML or JSON.
+    pub lqip: String,
+}
+
+pub fn placeholder(data: &[u8]) -> Result<Placeholder, String> {
+    let img = image::load_from_memory(data).map_err(|e| format!("Decode failed: {}", e))?;
+
+    let small = shrink(&img, BLURHASH_SIZE).to_rgba8();
+    // Spend more components along the longer side.
+    let (cx, cy) = if small.width() >= small.height() { (4, 3) } else { (3, 4) };
+    let blurhash = blurhash::encode(cx, cy, small.width(), small.height(), small.as_raw())
+        .map_err(|e| format!("BlurHash failed: {}", e))?;
+
+    // At this size PNG beats JPEG, whose headers alone outweigh the pixels.
+    let tiny = shrink(&img, LQIP_SIZE);
+    let tiny = if tiny.color().has_alpha() {
+        DynamicImage::ImageRgba8(tiny.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(tiny.to_rgb8())
+    };
+
+    let mut encoded = Vec::new();
+    tiny.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+        .map_err(|e| format!("Encode failed: {}", e))?;
+
+    Ok(Placeholder {
+        blurhash,
+        lqip: format!("data:image/png;base64,{}", STANDARD.encode(&encoded)),
+    })
+}
+
+fn shrink(img: &DynamicImage, size: u32) -> DynamicImage {
+    if img.width() <= size && img.height() <= size {
+        return img.clone();
+    }
+    img.resize(size, size, FilterType::Triangle)
+}
@@ -0,0 +1,247 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::error::ImageResult;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageReader};
+use std::io::Cursor;
+
+const JPEG_QUALITY: u8 = 90;
+
+pub struct SanitizedImage {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub format: &'static str,
+    pub mime: &'static str,
+}
+
+/// Removes EXIF, XMP and IPTC metadata (GPS position, camera serials, ...)
+/// from an uploaded image and bakes its EXIF orientation into the pixels.
+///
+/// JPEG, PNG and WebP images that carry no such metadata are returned
+/// byte-for-byte; anything else is decoded and re-encoded, which drops every
+/// metadata block except the ICC colour profile. Other formats are always
+/// re-encoded because their decoders do not report every kind of metadata
+/// (TIFF and AVIF EXIF among them). GIFs keep their format and frames and only
+/// lose their comment and metadata extensions.
+pub fn sanitize(source: Vec<u8>) -> Result<SanitizedImage, String> {
+    let reader = ImageReader::new(Cursor::new(source.as_slice()))
+        .with_guessed_format()
+        .map_err(|e| format!("Unreadable image: {}", e))?;
+    let format = reader.format().ok_or("Unrecognised image format")?;
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|e| format!("Unsupported image: {}", e))?;
+
+    let exif = decoder.exif_metadata().map_err(|e| format!("Unreadable metadata: {}", e))?;
+    let xmp = decoder.xmp_metadata().map_err(|e| format!("Unreadable metadata: {}", e))?;
+    let iptc = decoder.iptc_metadata().map_err(|e| format!("Unreadable metadata: {}", e))?;
+    let icc = decoder.icc_profile().map_err(|e| format!("Unreadable metadata: {}", e))?;
+    let orientation = exif
+        .as_deref()
+        .and_then(Orientation::from_exif_chunk)
+        .unwrap_or(Orientation::NoTransforms);
+
+    if format == ImageFormat::Gif {
+        let (width, height) = decoder.dimensions();
+        drop(decoder);
+        return Ok(SanitizedImage {
+            data: strip_gif(&source)?,
+            width,
+            height,
+            format: format_name(format),
+            mime: format.to_mime_type(),
+        });
+    }
+
+    let reports_metadata = matches!(format, ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP);
+    if reports_metadata && exif.is_none() && xmp.is_none() && iptc.is_none() {
+        let (width, height) = decoder.dimensions();
+        drop(decoder);
+        return Ok(SanitizedImage {
+            data: source,
+            width,
+            height,
+            format: format_name(format),
+            mime: format.to_mime_type(),
+        });
+    }
+
+    let mut img = DynamicImage::from_decoder(decoder).map_err(|e| format!("Decode failed: {}", e))?;
+    img.apply_orientation(orientation);
+
+    let output = match format {
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP => format,
+        // Only a handful of encoders are worth using here; everything else becomes PNG.
+        _ => ImageFormat::Png,
+    };
+
+    let mut data = Vec::new();
+    let encoded = match output {
+        ImageFormat::Jpeg => encode(
+            &DynamicImage::ImageRgb8(img.to_rgb8()),
+            JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY),
+            icc,
+        ),
+        ImageFormat::WebP => encode(
+            &DynamicImage::ImageRgba8(img.to_rgba8()),
+            WebPEncoder::new_lossless(&mut data),
+            icc,
+        ),
+        _ => encode(&img, PngEncoder::new(&mut data), icc),
+    };
+    encoded.map_err(|e| format!("Encode failed: {}", e))?;
+
+    Ok(SanitizedImage {
+        data,
+        width: img.width(),
+        height: img.height(),
+        format: format_name(output),
+        mime: output.to_mime_type(),
+    })
+}
+
+/// Writes `img` with `encoder`, carrying the source's colour profile over.
+fn encode(img: &DynamicImage, mut encoder: impl ImageEncoder, icc: Option<Vec<u8>>) -> ImageResult<()> {
+    if let Some(icc) = icc {
+        encoder.set_icc_profile(icc).map_err(ImageError::Unsupported)?;
+    }
+    img.write_with_encoder(encoder)
+}
+
+/// Copies a GIF block by block, dropping comments and every application
+/// extension except looping (`NETSCAPE2.0`, `ANIMEXTS1.0`) and the colour
+/// profile (`ICCRGBG1012`). XMP lives in an application extension, and
+/// anything after the trailer is dropped too.
+fn strip_gif(source: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "Truncated GIF".to_string();
+    // A colour table follows a descriptor whose flags have the top bit set.
+    let table_len = |flags: u8| if flags & 0x80 != 0 { 3 << ((flags & 0x07) + 1) } else { 0 };
+    // Data sub-blocks run until a zero length byte; returns the position after it.
+    let sub_blocks = |mut at: usize| loop {
+        let len = *source.get(at).ok_or_else(truncated)? as usize;
+        at += 1 + len;
+        if len == 0 {
+            return Ok::<_, String>(at);
+        }
+    };
+
+    let screen = source.get(..13).ok_or_else(truncated)?;
+    let mut pos = 13 + table_len(screen[10]);
+    let mut out = source.get(..pos).ok_or_else(truncated)?.to_vec();
+
+    loop {
+        let end = match *source.get(pos).ok_or_else(truncated)? {
+            0x3B => {
+                out.push(0x3B);
+                return Ok(out);
+            }
+            0x2C => {
+                let descriptor = source.get(pos..pos + 10).ok_or_else(truncated)?;
+                // Skip the local colour table and the LZW minimum code size.
+                let end = sub_blocks(pos + 10 + table_len(descriptor[9]) + 1)?;
+                out.extend_from_slice(source.get(pos..end).ok_or_else(truncated)?);
+                end
+            }
+            0x21 => {
+                let end = sub_blocks(pos + 2)?;
+                let block = source.get(pos..end).ok_or_else(truncated)?;
+                let keep = match block[1] {
+                    0xFE => false,
+                    0xFF => matches!(block.get(3..14), Some(b"NETSCAPE2.0" | b"ANIMEXTS1.0" | b"ICCRGBG1012")),
+                    _ => true,
+                };
+                if keep {
+                    out.extend_from_slice(block);
+                }
+                end
+            }
+            other => return Err(format!("Unexpected GIF block 0x{:02x}", other)),
+        };
+        pos = end;
+    }
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    format.extensions_str().last().copied().unwrap_or("unknown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 2, image::Rgb([200, 10, 10])));
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    #[test]
+    fn passes_clean_png_through_unchanged() {
+        let source = encode(ImageFormat::Png);
+        let sanitized = sanitize(source.clone()).unwrap();
+        assert_eq!(sanitized.data, source);
+        assert_eq!((sanitized.width, sanitized.height, sanitized.mime), (3, 2, "image/png"));
+    }
+
+    #[test]
+    fn keeps_the_colour_profile_when_reencoding() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 2, image::Rgb([200, 10, 10])));
+        let mut source = Vec::new();
+        let mut encoder = PngEncoder::new(&mut source);
+        encoder.set_icc_profile(b"test colour profile".to_vec()).unwrap();
+        encoder.set_exif_metadata(b"MM\0\x2a\0\0\0\x08\0\0".to_vec()).unwrap();
+        img.write_with_encoder(encoder).unwrap();
+
+        let sanitized = sanitize(source.clone()).unwrap();
+        assert_ne!(sanitized.data, source);
+        let mut decoder = ImageReader::new(Cursor::new(sanitized.data))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap();
+        assert_eq!(decoder.icc_profile().unwrap().as_deref(), Some(&b"test colour profile"[..]));
+        assert!(decoder.exif_metadata().unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_animated_gifs_and_strips_their_comments_and_xmp() {
+        use image::codecs::gif::{GifEncoder, Repeat};
+        use image::{Delay, Frame, RgbaImage};
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for colour in [[255, 0, 0, 255], [0, 0, 255, 255]] {
+                let pixels = RgbaImage::from_pixel(3, 2, image::Rgba(colour));
+                let frame = Frame::from_parts(pixels, 0, 0, Delay::from_numer_denom_ms(100, 1));
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+
+        // Splice a comment and an XMP packet in before the trailer.
+        let mut source = gif[..gif.len() - 1].to_vec();
+        source.extend_from_slice(b"\x21\xfe\x07shot on\x00");
+        source.extend_from_slice(b"\x21\xff\x0bXMP DataXMP\x05<gps>\x00");
+        source.push(0x3B);
+
+        let sanitized = sanitize(source).unwrap();
+        assert_eq!(sanitized.data, gif);
+        assert_eq!((sanitized.width, sanitized.height, sanitized.format, sanitized.mime), (3, 2, "gif", "image/gif"));
+
+        let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(sanitized.data)).unwrap();
+        assert_eq!(image::AnimationDecoder::into_frames(decoder).count(), 2);
+    }
+
+    #[test]
+    fn always_reencodes_formats_without_full_metadata_reporting() {
+        let source = encode(ImageFormat::Tiff);
+        let sanitized = sanitize(source.clone()).unwrap();
+        assert_ne!(sanitized.data, source);
+        assert_eq!((sanitized.width, sanitized.height, sanitized.mime), (3, 2, "image/png"));
+    }
+}
@@ -0,0 +1,162 @@
+use async_trait::async_trait;
+use std::env;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+use super::MediaStorage;
+
+/// Stores blobs under `MEDIA_FS_ROOT` (default `./media`), fanned out as `ab/cd/<hash>`.
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::var("MEDIA_FS_ROOT").unwrap_or_else(|_| "./media".to_string()))
+    }
+
+    fn path_for(&self, hash: &str) -> Result<PathBuf, String> {
+        if hash.len() < 4 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("Invalid blob hash: {}", hash));
+        }
+        Ok(self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash))
+    }
+}
+
+#[async_trait]
+impl MediaStorage for FsStorage {
+    fn name(&self) -> &'static str {
+        "fs"
+    }
+
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path_for(hash)?;
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        let dir = path.parent().expect("blob path has a parent");
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+        // Write to a temporary name first so readers never see a partial blob.
+        let tmp = dir.join(format!(".{}.{}", hash, Uuid::new_v4()));
+        tokio::fs::write(&tmp, data)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| format!("Failed to move blob into place: {}", e))
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path_for(hash)?;
+        match tokio::fs::read(&path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    async fn get_range(&self, hash: &str, start: u64, end: u64) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path_for(hash)?;
+        let mut file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+        };
+
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(start))
+            .await
+            .map_err(|e| format!("Failed to seek {}: {}", path.display(), e))?;
+        file.take(end.saturating_sub(start) + 1)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(Some(data))
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), String> {
+        let path = self.path_for(hash)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete {}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789";
+
+    fn temp_storage() -> (FsStorage, PathBuf) {
+        let root = env::temp_dir().join(format!("engine-fs-{}", Uuid::new_v4()));
+        (FsStorage::new(&root), root)
+    }
+
+    #[tokio::test]
+    async fn stores_blobs_fanned_out_by_hash() {
+        let (storage, root) = temp_storage();
+
+        storage.put(HASH, b"first").await.unwrap();
+        assert!(root.join("ab").join("cd").join(HASH).is_file());
+        assert_eq!(storage.get(HASH).await.unwrap().as_deref(), Some(&b"first"[..]));
+
+        // Blobs are content-addressed, so a second put of the same hash is a no-op.
+        storage.put(HASH, b"second").await.unwrap();
+        assert_eq!(storage.get(HASH).await.unwrap().as_deref(), Some(&b"first"[..]));
+
+        storage.delete(HASH).await.unwrap();
+        assert!(storage.get(HASH).await.unwrap().is_none());
+        storage.delete(HASH).await.unwrap();
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn reads_byte_ranges() {
+        let (storage, root) = temp_storage();
+        storage.put(HASH, b"0123456789").await.unwrap();
+
+        assert_eq!(storage.get_range(HASH, 2, 5).await.unwrap().as_deref(), Some(&b"2345"[..]));
+        assert_eq!(storage.get_range(HASH, 8, 20).await.unwrap().as_deref(), Some(&b"89"[..]));
+        assert!(storage.get_range(&HASH.replace('a', "b"), 0, 1).await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn leaves_no_temporary_files() {
+        let (storage, root) = temp_storage();
+        storage.put(HASH, b"data").await.unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(root.join("ab").join("cd"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec![std::ffi::OsString::from(HASH)]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn rejects_hashes_that_are_not_hex() {
+        let (storage, root) = temp_storage();
+
+        for hash in ["../../etc/passwd", "ab", "abcd/../x", ""] {
+            assert!(storage.put(hash, b"data").await.is_err(), "{}", hash);
+            assert!(storage.get(hash).await.is_err(), "{}", hash);
+        }
+        assert!(!root.exists());
+    }
+}
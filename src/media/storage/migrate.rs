@@ -0,0 +1,173 @@
+use sqlx::PgPool;
+use super::{open, MediaStorage};
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub moved: u64,
+    pub missing: Vec<String>,
+}
+
+/// Copies every blob indexed under backend `from` into backend `to`, repoints
+/// the index and deletes the original. Blobs are moved one at a time, so an
+/// interrupted run can simply be started again.
+pub async fn migrate(pool: &PgPool, from: &str, to: &str) -> Result<MigrationReport, String> {
+    if from == to {
+        return Err("Source and destination backends are the same".into());
+    }
+
+    let source = open(from, pool)?;
+    let target = open(to, pool)?;
+    migrate_between(pool, source.as_ref(), target.as_ref()).await
+}
+
+/// Moves every blob indexed under `source` into `target`.
+async fn migrate_between(
+    pool: &PgPool,
+    source: &dyn MediaStorage,
+    target: &dyn MediaStorage,
+) -> Result<MigrationReport, String> {
+    let (from, to) = (source.name(), target.name());
+    let mut report = MigrationReport::default();
+
+    let hashes = sqlx::query_scalar!(
+        r#"
+        SELECT hash FROM public.media_blobs
+        WHERE storage = $1
+        ORDER BY hash
+        "#,
+        from
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Blob listing failed: {}", e))?;
+
+    for hash in hashes {
+        let Some(data) = source.get(&hash).await? else {
+            report.missing.push(hash);
+            continue;
+        };
+
+        target.put(&hash, &data).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE public.media_blobs
+            SET storage = $1
+            WHERE hash = $2 AND storage = $3
+            "#,
+            to,
+            hash,
+            from
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Blob index update failed: {}", e))?;
+
+        source.delete(&hash).await?;
+        report.moved += 1;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::media::storage::fs::FsStorage;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    /// In-memory backend under a unique name, so only this test's index rows are touched.
+    struct MemoryStorage {
+        name: &'static str,
+        blobs: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl MediaStorage for MemoryStorage {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn put(&self, hash: &str, data: &[u8]) -> Result<(), String> {
+            self.blobs.lock().unwrap().insert(hash.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+            Ok(self.blobs.lock().unwrap().get(hash).cloned())
+        }
+
+        async fn delete(&self, hash: &str) -> Result<(), String> {
+            self.blobs.lock().unwrap().remove(hash);
+            Ok(())
+        }
+    }
+
+    fn random_hash() -> String {
+        format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+    }
+
+    #[tokio::test]
+    async fn moves_blobs_and_repoints_the_index() {
+        let pool = test_pool().await;
+        let source = MemoryStorage {
+            name: Box::leak(format!("memory-{}", Uuid::new_v4()).into_boxed_str()),
+            blobs: Mutex::new(HashMap::new()),
+        };
+        let root = std::env::temp_dir().join(format!("engine-migrate-{}", Uuid::new_v4()));
+        let target = FsStorage::new(&root);
+
+        let present = random_hash();
+        let missing = random_hash();
+        source.put(&present, b"payload").await.unwrap();
+        for hash in [&present, &missing] {
+            sqlx::query!(
+                r#"
+                INSERT INTO public.media_blobs (hash, size, storage, created_at)
+                VALUES ($1, 7, $2, 0)
+                "#,
+                hash,
+                source.name()
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let report = migrate_between(&pool, &source, &target).await.unwrap();
+
+        assert_eq!(report.moved, 1);
+        assert_eq!(report.missing, vec![missing.clone()]);
+        assert_eq!(target.get(&present).await.unwrap().as_deref(), Some(&b"payload"[..]));
+        assert!(source.get(&present).await.unwrap().is_none());
+
+        let backend = sqlx::query_scalar!("SELECT storage FROM public.media_blobs WHERE hash = $1", present)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(backend, "fs");
+
+        // The missing blob stays indexed under the source so it can be investigated.
+        let backend = sqlx::query_scalar!("SELECT storage FROM public.media_blobs WHERE hash = $1", missing)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(backend, source.name());
+
+        sqlx::query!("DELETE FROM public.media_blobs WHERE hash = ANY($1)", &[present, missing][..])
+            .execute(&pool)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_migrate_onto_itself() {
+        let pool = test_pool().await;
+        assert!(migrate(&pool, "postgres", "postgres").await.is_err());
+    }
+}
@@ -0,0 +1,58 @@
+pub mod fs;
+pub mod migrate;
+pub mod postgres;
+pub mod s3;
+
+use async_trait::async_trait;
+use sqlx::{PgConnection, PgPool};
+use std::env;
+use std::sync::Arc;
+
+/// Where blob payloads live. Blobs are addressed by their SHA-256 hex digest,
+/// so `put` is idempotent and implementations never need to handle updates.
+#[async_trait]
+pub trait MediaStorage: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), String>;
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String>;
+    async fn delete(&self, hash: &str) -> Result<(), String>;
+
+    /// Bytes `start..=end` of a blob, clamped to its length. Backends that can
+    /// read part of an object override this instead of loading all of it.
+    async fn get_range(&self, hash: &str, start: u64, end: u64) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.get(hash).await?.map(|data| {
+            let end = (end.saturating_add(1) as usize).min(data.len());
+            data.get(start as usize..end).unwrap_or_default().to_vec()
+        }))
+    }
+
+    /// Whether `put_in` writes through the caller's connection, so payloads
+    /// roll back with it.
+    fn transactional(&self) -> bool {
+        false
+    }
+
+    /// Like `put`, but backends that live in the database write through `conn`
+    /// so the payload commits or rolls back together with its index entry.
+    async fn put_in(&self, _conn: &mut PgConnection, hash: &str, data: &[u8]) -> Result<(), String> {
+        self.put(hash, data).await
+    }
+}
+
+pub type SharedStorage = Arc<dyn MediaStorage>;
+
+/// Builds the backend called `name` (`postgres`, `fs` or `s3`) from its environment settings.
+pub fn open(name: &str, pool: &PgPool) -> Result<SharedStorage, String> {
+    match name {
+        "postgres" => Ok(Arc::new(postgres::PostgresStorage::new(pool.clone()))),
+        "fs" => Ok(Arc::new(fs::FsStorage::from_env())),
+        "s3" => Ok(Arc::new(s3::S3Storage::from_env()?)),
+        other => Err(format!("Unknown media storage backend: {}", other)),
+    }
+}
+
+/// Opens the backend new blobs are written to, chosen by `MEDIA_STORAGE`.
+pub fn from_env(pool: &PgPool) -> Result<SharedStorage, String> {
+    let name = env::var("MEDIA_STORAGE").unwrap_or_else(|_| "postgres".to_string());
+    open(&name, pool)
+}
@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use super::MediaStorage;
+
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn insert(executor: impl PgExecutor<'_>, hash: &str, data: &[u8]) -> Result<(), String> {
+        sqlx::query!(
+            r#"
+            INSERT INTO public.media_blob_data (hash, data)
+            VALUES ($1, $2)
+            ON CONFLICT (hash) DO NOTHING
+            "#,
+            hash,
+            data
+        )
+        .execute(executor)
+        .await
+        .map_err(|e| format!("Blob insert failed: {}", e))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MediaStorage for PostgresStorage {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn transactional(&self) -> bool {
+        true
+    }
+
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), String> {
+        Self::insert(&self.pool, hash, data).await
+    }
+
+    async fn put_in(&self, conn: &mut PgConnection, hash: &str, data: &[u8]) -> Result<(), String> {
+        Self::insert(conn, hash, data).await
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT data FROM public.media_blob_data
+            WHERE hash = $1
+            "#,
+            hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| format!("Blob fetch failed: {}", e))
+    }
+
+    async fn get_range(&self, hash: &str, start: u64, end: u64) -> Result<Option<Vec<u8>>, String> {
+        let from = i32::try_from(start + 1).map_err(|_| "Range start out of bounds".to_string())?;
+        let count = i32::try_from(end.saturating_sub(start) + 1).unwrap_or(i32::MAX);
+        sqlx::query_scalar!(
+            r#"
+            SELECT substring(data FROM $2 FOR $3) AS "data!" FROM public.media_blob_data
+            WHERE hash = $1
+            "#,
+            hash,
+            from,
+            count
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| format!("Blob fetch failed: {}", e))
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), String> {
+        sqlx::query!(
+            r#"
+            DELETE FROM public.media_blob_data
+            WHERE hash = $1
+            "#,
+            hash
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| format!("Blob delete failed: {}", e))?;
+
+        Ok(())
+    }
+}
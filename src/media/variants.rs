@@ -0,0 +1,331 @@
+use hmac::{Hmac, Mac};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::env;
+use std::io::Cursor;
+
+const MAX_DIMENSION: u32 = 4096;
+const DEFAULT_WIDTHS: &[u32] = &[160, 320, 640, 960, 1280, 1920];
+
+#[derive(Debug, Deserialize)]
+pub struct VariantQuery {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<String>,
+    pub fmt: Option<String>,
+    pub sig: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio.
+    Contain,
+    /// Scale to cover the box and crop the overflow from the centre.
+    Cover,
+    /// Stretch to exactly the requested box.
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::Webp),
+            "avif" => Ok(OutputFormat::Avif),
+            other => Err(format!("Unsupported format: {}", other)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Webp => ImageFormat::WebP,
+            OutputFormat::Avif => ImageFormat::Avif,
+        }
+    }
+
+    /// Picks an output format matching the source, falling back to JPEG for
+    /// sources we cannot (or should not) re-encode as-is.
+    fn for_source(format: Option<ImageFormat>) -> Self {
+        match format {
+            Some(ImageFormat::Png) | Some(ImageFormat::Gif) => OutputFormat::Png,
+            Some(ImageFormat::WebP) => OutputFormat::Webp,
+            Some(ImageFormat::Avif) => OutputFormat::Avif,
+            _ => OutputFormat::Jpeg,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantSpec {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub format: Option<OutputFormat>,
+}
+
+pub struct Rendered {
+    pub data: Vec<u8>,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl VariantSpec {
+    /// Returns `None` when the query asks for the original image.
+    pub fn parse(query: &VariantQuery) -> Result<Option<Self>, String> {
+        if query.w.is_none() && query.h.is_none() && query.fmt.is_none() && query.fit.is_none() {
+            return Ok(None);
+        }
+
+        for dim in [query.w, query.h].into_iter().flatten() {
+            if dim == 0 || dim > MAX_DIMENSION {
+                return Err(format!("Dimensions must be between 1 and {}", MAX_DIMENSION));
+            }
+        }
+
+        let fit = match query.fit.as_deref() {
+            None | Some("contain") => Fit::Contain,
+            Some("cover") => Fit::Cover,
+            Some("fill") => Fit::Fill,
+            Some(other) => return Err(format!("Unsupported fit: {}", other)),
+        };
+
+        if fit != Fit::Contain && (query.w.is_none() || query.h.is_none()) {
+            return Err("Crop modes need both w and h".into());
+        }
+
+        Ok(Some(Self {
+            width: query.w,
+            height: query.h,
+            fit,
+            format: query.fmt.as_deref().map(OutputFormat::parse).transpose()?,
+        }))
+    }
+
+    /// Canonical form used as the cache key and as the signed message.
+    pub fn key(&self) -> String {
+        let fit = match self.fit {
+            Fit::Contain => "contain",
+            Fit::Cover => "cover",
+            Fit::Fill => "fill",
+        };
+        format!(
+            "w={}&h={}&fit={}&fmt={}",
+            self.width.map(|w| w.to_string()).unwrap_or_default(),
+            self.height.map(|h| h.to_string()).unwrap_or_default(),
+            fit,
+            self.format.map(OutputFormat::name).unwrap_or("auto"),
+        )
+    }
+
+    /// Width-only resizes to one of the `IMAGE_VARIANT_WIDTHS` presets are open
+    /// to everyone; anything else needs a signature minted with `IMAGE_SIGNING_KEY`.
+    pub fn authorize(&self, image_id: &str, sig: Option<&str>) -> Result<(), String> {
+        if self.height.is_none() && self.width.is_none_or(|w| allowed_widths().contains(&w)) {
+            return Ok(());
+        }
+
+        let sig = sig.ok_or("This variant requires a signature")?;
+        let sig = hex::decode(sig).map_err(|_| "Malformed signature".to_string())?;
+        let key = signing_key()?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(image_id.as_bytes());
+        mac.update(b"?");
+        mac.update(self.key().as_bytes());
+        mac.verify_slice(&sig).map_err(|_| "Invalid signature".to_string())
+    }
+
+    pub fn render(&self, source: &[u8]) -> Result<Rendered, String> {
+        let format = image::guess_format(source).ok();
+        let img = image::load_from_memory(source).map_err(|e| format!("Decode failed: {}", e))?;
+
+        let img = self.resize(img);
+        let output = self.format.unwrap_or(OutputFormat::for_source(format));
+        let img = match output {
+            // Neither encoder accepts an alpha channel or 16-bit samples.
+            OutputFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8()),
+            OutputFormat::Webp | OutputFormat::Avif => DynamicImage::ImageRgba8(img.to_rgba8()),
+            OutputFormat::Png => img,
+        };
+
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), output.image_format())
+            .map_err(|e| format!("Encode failed: {}", e))?;
+
+        Ok(Rendered {
+            data,
+            format: output,
+            width: img.width(),
+            height: img.height(),
+        })
+    }
+
+    fn resize(&self, img: DynamicImage) -> DynamicImage {
+        // Never upscale: clamp the requested box to the source dimensions.
+        let width = self.width.unwrap_or(u32::MAX).min(img.width());
+        let height = self.height.unwrap_or(u32::MAX).min(img.height());
+        if width == img.width() && height == img.height() {
+            return img;
+        }
+
+        match self.fit {
+            Fit::Contain => img.resize(width, height, FilterType::Lanczos3),
+            Fit::Cover => img.resize_to_fill(width, height, FilterType::Lanczos3),
+            Fit::Fill => img.resize_exact(width, height, FilterType::Lanczos3),
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn signing_key() -> Result<String, String> {
+    env::var("IMAGE_SIGNING_KEY").map_err(|_| "Signed variants are not enabled".to_string())
+}
+
+/// Tests sign with a fixed key rather than mutating the process environment.
+#[cfg(test)]
+fn signing_key() -> Result<String, String> {
+    Ok(env::var("IMAGE_SIGNING_KEY").unwrap_or_else(|_| "test-signing-key".to_string()))
+}
+
+fn allowed_widths() -> Vec<u32> {
+    env::var("IMAGE_VARIANT_WIDTHS")
+        .ok()
+        .map(|v| v.split(',').filter_map(|w| w.trim().parse().ok()).collect())
+        .unwrap_or_else(|| DEFAULT_WIDTHS.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgb, RgbImage};
+
+    fn query(w: Option<u32>, h: Option<u32>, fit: Option<&str>, fmt: Option<&str>) -> VariantQuery {
+        VariantQuery {
+            w,
+            h,
+            fit: fit.map(str::to_string),
+            fmt: fmt.map(str::to_string),
+            sig: None,
+        }
+    }
+
+    fn spec(w: Option<u32>, h: Option<u32>, fit: Option<&str>) -> VariantSpec {
+        VariantSpec::parse(&query(w, h, fit, None)).unwrap().unwrap()
+    }
+
+    fn sign(image_id: &str, spec: &VariantSpec) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"test-signing-key").unwrap();
+        mac.update(format!("{}?{}", image_id, spec.key()).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// 40x20, red on the left half and blue on the right.
+    fn source() -> Vec<u8> {
+        let img = RgbImage::from_fn(40, 20, |x, _| if x < 20 { Rgb([255, 0, 0]) } else { Rgb([0, 0, 255]) });
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(img).write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn parses_variant_queries() {
+        assert_eq!(VariantSpec::parse(&query(None, None, None, None)).unwrap(), None);
+        assert_eq!(
+            VariantSpec::parse(&query(Some(320), None, None, Some("webp"))).unwrap(),
+            Some(VariantSpec { width: Some(320), height: None, fit: Fit::Contain, format: Some(OutputFormat::Webp) })
+        );
+        assert_eq!(spec(Some(10), Some(20), Some("cover")).key(), "w=10&h=20&fit=cover&fmt=auto");
+
+        for bad in [
+            query(Some(0), None, None, None),
+            query(Some(MAX_DIMENSION + 1), None, None, None),
+            query(Some(10), None, Some("cover"), None),
+            query(Some(10), Some(10), Some("squash"), None),
+            query(Some(10), None, None, Some("bmp")),
+        ] {
+            assert!(VariantSpec::parse(&bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn only_preset_widths_go_unsigned() {
+        assert!(spec(Some(320), None, None).authorize("img", None).is_ok());
+        assert!(spec(Some(321), None, None).authorize("img", None).is_err());
+        assert!(spec(Some(320), Some(320), None).authorize("img", None).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_signatures() {
+        let signed = spec(Some(100), Some(100), Some("cover"));
+        let sig = sign("img", &signed);
+        assert!(signed.authorize("img", Some(&sig)).is_ok());
+
+        // The signature covers the image and every parameter.
+        assert!(signed.authorize("other", Some(&sig)).is_err());
+        assert!(spec(Some(100), Some(100), Some("fill")).authorize("img", Some(&sig)).is_err());
+        assert!(spec(Some(1000), Some(100), Some("cover")).authorize("img", Some(&sig)).is_err());
+
+        let mut flipped = sig.clone().into_bytes();
+        flipped[0] = if flipped[0] == b'0' { b'1' } else { b'0' };
+        assert!(signed.authorize("img", Some(std::str::from_utf8(&flipped).unwrap())).is_err());
+        assert!(signed.authorize("img", Some("not hex")).is_err());
+    }
+
+    #[test]
+    fn renders_the_requested_width_and_crop() {
+        let contained = spec(Some(10), None, None).render(&source()).unwrap();
+        assert_eq!((contained.width, contained.height, contained.format), (10, 5, OutputFormat::Png));
+
+        // Cover scales to 20x10 and keeps the middle 10x10: half red, half blue.
+        let covered = spec(Some(10), Some(10), Some("cover")).render(&source()).unwrap();
+        assert_eq!((covered.width, covered.height), (10, 10));
+        let img = image::load_from_memory(&covered.data).unwrap();
+        assert_eq!(img.dimensions(), (10, 10));
+        assert!(img.get_pixel(1, 5)[0] > 200 && img.get_pixel(1, 5)[2] < 50);
+        assert!(img.get_pixel(8, 5)[2] > 200 && img.get_pixel(8, 5)[0] < 50);
+
+        let filled = spec(Some(10), Some(10), Some("fill")).render(&source()).unwrap();
+        assert_eq!((filled.width, filled.height), (10, 10));
+
+        // Never upscaled.
+        let original = spec(Some(400), None, None).render(&source()).unwrap();
+        assert_eq!((original.width, original.height), (40, 20));
+
+        let jpeg = VariantSpec::parse(&query(Some(10), None, None, Some("jpg"))).unwrap().unwrap();
+        let rendered = jpeg.render(&source()).unwrap();
+        assert_eq!(rendered.format, OutputFormat::Jpeg);
+        assert_eq!(image::guess_format(&rendered.data).unwrap(), ImageFormat::Jpeg);
+    }
+}
@@ -0,0 +1,128 @@
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use super::audio_meta::open;
+
+/// Resolution peaks are stored at; requests can only ask for this many points or fewer.
+pub const STORED_POINTS: usize = 4096;
+
+/// Frames folded into one intermediate min/max pair while decoding, so long
+/// episodes never have to be held in memory as raw samples.
+const BLOCK_FRAMES: usize = 256;
+
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub samples_per_point: u32,
+    /// Interleaved `min, max` pairs scaled to the signed 8-bit range.
+    pub data: Vec<i8>,
+}
+
+impl Waveform {
+    pub fn points(&self) -> usize {
+        self.data.len() / 2
+    }
+
+    /// Merges neighbouring pairs so that at most `points` remain.
+    pub fn resample(&self, points: usize) -> Waveform {
+        let points = points.clamp(1, self.points().max(1));
+        let factor = self.points().div_ceil(points).max(1);
+
+        let data = self
+            .data
+            .chunks(2 * factor)
+            .flat_map(|chunk| {
+                let min = chunk.iter().step_by(2).copied().min().unwrap_or(0);
+                let max = chunk.iter().skip(1).step_by(2).copied().max().unwrap_or(0);
+                [min, max]
+            })
+            .collect();
+
+        Waveform {
+            sample_rate: self.sample_rate,
+            samples_per_point: self.samples_per_point * factor as u32,
+            data,
+        }
+    }
+}
+
+/// Decodes the default track and reduces it to `STORED_POINTS` min/max pairs,
+/// folding all channels together.
+pub fn generate(data: Vec<u8>, mime: Option<&str>) -> Result<Waveform, String> {
+    let mut probed = open(data, mime)?;
+    let track = probed.format.default_track().ok_or("No audio track found")?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.ok_or("Unknown sample rate")?;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+
+    let mut blocks: Vec<(f32, f32)> = Vec::new();
+    let mut current = (0f32, 0f32);
+    let mut in_block = 0usize;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(_)) => break,
+            Err(e) => return Err(format!("Demux failed: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet only loses its own samples.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Decode failed: {}", e)),
+        };
+
+        let channels = decoded.spec().channels.count().max(1);
+        let buf = buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        if buf.capacity() < decoded.capacity() * channels {
+            *buf = SampleBuffer::new(decoded.capacity() as u64, *decoded.spec());
+        }
+        buf.copy_interleaved_ref(decoded);
+
+        for frame in buf.samples().chunks(channels) {
+            for &sample in frame {
+                current.0 = current.0.min(sample);
+                current.1 = current.1.max(sample);
+            }
+            in_block += 1;
+            if in_block == BLOCK_FRAMES {
+                blocks.push(current);
+                current = (0.0, 0.0);
+                in_block = 0;
+            }
+        }
+    }
+    if in_block > 0 {
+        blocks.push(current);
+    }
+    if blocks.is_empty() {
+        return Err("No audio could be decoded".into());
+    }
+
+    let per_point = blocks.len().div_ceil(STORED_POINTS);
+    let data = blocks
+        .chunks(per_point)
+        .flat_map(|chunk| {
+            let min = chunk.iter().map(|b| b.0).fold(0.0, f32::min);
+            let max = chunk.iter().map(|b| b.1).fold(0.0, f32::max);
+            [scale(min), scale(max)]
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Waveform {
+        sample_rate,
+        samples_per_point: (per_point * BLOCK_FRAMES) as u32,
+        data,
+    })
+}
+
+fn scale(sample: f32) -> i8 {
+    (sample.clamp(-1.0, 1.0) * 127.0).round() as i8
+}
@@ -1,19 +1,130 @@
 // use axum::{Router, routing::{get, post}, middleware};
-use axum::{Router, routing::{get, post}};
+use axum::{Router, middleware, routing::{delete, get, post}};
+use crate::authz::{require_role, Role};
 use crate::api::auth_handler::handler as google;
+use crate::api::auth_handler::provider as oidc;
+use crate::api::email_handler::request as email;
+use crate::api::email_handler::verify as emailverify;
+use crate::api::session_handler::refresh;
+use crate::api::session_handler::logout;
+use crate::api::session_handler::logout_all as logoutall;
+use crate::api::session_handler::revoke as sessionrevoke;
+use crate::api::session_handler::revoke_user as usersessionsrevoke;
+use crate::api::session_selector::selector as sessions;
+use crate::api::session_selector::user_sessions as usersessions;
+use crate::api::user_selector::me;
+use crate::api::user_handler::role as userrole;
+use crate::api::identity_handler::link as identitylink;
+use crate::api::identity_handler::unlink as identityunlink;
+use crate::api::identity_selector::selector as identities;
+use crate::api::api_key_handler::create as apikeycreate;
+use crate::api::api_key_handler::revoke as apikeyrevoke;
+use crate::api::api_key_selector::selector as apikeys;
 use crate::api::article_handler::handler as article;
 use crate::api::glossary_handler::handler as glossary;
 use crate::api::image_handler::handler as image;
+use crate::api::image_selector::selector as imageselector;
+use crate::api::image_selector::meta as imagemeta;
 use crate::api::audio_handler::handler as audio;
+use crate::api::audio_selector::selector as audioselector;
+use crate::api::audio_selector::waveform as audiowaveform;
+use crate::api::transcript_handler::handler as transcript;
+use crate::api::transcript_handler::chapters as chapters;
+use crate::api::transcript_selector::selector as transcriptselector;
+use crate::api::transcript_selector::chapters as chapterselector;
+use crate::api::search_selector::selector as search;
+use crate::api::episode_handler::handler as episode;
+use crate::api::podcast_feed::feed as podcastfeed;
+use crate::api::media_handler::gc as mediagc;
+use crate::api::media_handler::backfill as mediabackfill;
+use crate::api::media_selector::backfill as backfillselector;
+use crate::api::job_selector::selector as jobselector;
+use crate::api::schedule_handler::host as schedulehost;
+use crate::api::schedule_handler::show as scheduleshow;
+use crate::api::schedule_handler::slot as scheduleslot;
+use crate::api::schedule_handler::delete_slot as scheduleslotdelete;
+use crate::api::schedule_handler::override_slot as scheduleoverride;
+use crate::api::schedule_handler::delete_override as scheduleoverridedelete;
+use crate::api::schedule_selector::now as schedulenow;
+use crate::api::schedule_selector::next as schedulenext;
+use crate::api::schedule_selector::week as scheduleweek;
+use crate::api::live_handler::handler as nowplaying;
+use crate::api::live_selector::now_playing as nowplayingselector;
+use crate::api::live_selector::history as livehistory;
+use crate::api::live_selector::events as liveevents;
+use crate::api::live_selector::socket as livesocket;
 use crate::api::glossary_selector::selector as glosselector;
 
+/// Routes needing a signed-in user with at least `role`.
+fn restricted(role: Role, router: Router) -> Router {
+    router.route_layer(middleware::from_fn_with_state(role, require_role))
+}
+
 pub fn routes() -> Router {
-    Router::new()
+    let public = Router::new()
         .route("/", get(|| async { "Server is running." }))
         .route("/google", post(google))
+        .route("/oidc/{provider}", post(oidc))
+        .route("/email", post(email))
+        .route("/email/verify", post(emailverify))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
+        .route("/image/{id}", get(imageselector))
+        .route("/image/{id}/meta", get(imagemeta))
+        .route("/audio/{id}", get(audioselector))
+        .route("/audio/{id}/waveform", get(audiowaveform))
+        .route("/audio/{id}/transcript", get(transcriptselector))
+        .route("/audio/{id}/chapters", get(chapterselector))
+        .route("/search", get(search))
+        .route("/podcast.xml", get(podcastfeed))
+        .route("/schedule/now", get(schedulenow))
+        .route("/schedule/next", get(schedulenext))
+        .route("/schedule/week", get(scheduleweek))
+        .route("/live/now-playing", get(nowplayingselector))
+        .route("/live/history", get(livehistory))
+        .route("/live/events", get(liveevents))
+        .route("/live/ws", get(livesocket))
+        .route("/glosselector", get(glosselector));
+
+    let reader = Router::new()
+        .route("/me", get(me))
+        .route("/me/identities", get(identities).post(identitylink))
+        .route("/me/identities/{id}", delete(identityunlink))
+        .route("/logout/all", post(logoutall))
+        .route("/sessions", get(sessions))
+        .route("/sessions/{id}", delete(sessionrevoke));
+
+    let author = Router::new()
         .route("/article", post(article))
-        .route("/glossary", post(glossary))
         .route("/image", post(image))
         .route("/audio", post(audio))
-        .route("/glosselector", get(glosselector))
+        .route("/jobs/{id}", get(jobselector));
+
+    let editor = Router::new()
+        .route("/glossary", post(glossary))
+        .route("/audio/{id}/transcript", post(transcript))
+        .route("/audio/{id}/chapters", post(chapters))
+        .route("/episode", post(episode))
+        .route("/schedule/host", post(schedulehost))
+        .route("/schedule/show", post(scheduleshow))
+        .route("/schedule/slot", post(scheduleslot))
+        .route("/schedule/slot/{id}", delete(scheduleslotdelete))
+        .route("/schedule/override", post(scheduleoverride))
+        .route("/schedule/override/{id}", delete(scheduleoverridedelete))
+        .route("/live/now-playing", post(nowplaying));
+
+    let admin = Router::new()
+        .route("/users/{id}/role", post(userrole))
+        .route("/users/{id}/sessions", get(usersessions).delete(usersessionsrevoke))
+        .route("/api-keys", get(apikeys).post(apikeycreate))
+        .route("/api-keys/{id}", delete(apikeyrevoke))
+        .route("/media/gc", post(mediagc))
+        .route("/media/backfill", post(mediabackfill))
+        .route("/media/backfill/{id}", get(backfillselector));
+
+    public
+        .merge(restricted(Role::Reader, reader))
+        .merge(restricted(Role::Author, author))
+        .merge(restricted(Role::Editor, editor))
+        .merge(restricted(Role::Admin, admin))
 }
\ No newline at end of file
@@ -0,0 +1,315 @@
+//! Expansion of recurring weekly slots and one-off overrides into concrete airings.
+//!
+//! Slots are stored as wall-clock times in their own time zone, so a show that
+//! starts at 20:00 in Europe/Berlin keeps starting at 20:00 local across DST
+//! changes. All computed airings are Unix timestamps.
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use sqlx::PgPool;
+
+#[derive(Debug, Clone)]
+pub struct Slot {
+    pub id: String,
+    pub show_id: String,
+    /// 0 = Monday ... 6 = Sunday.
+    pub weekday: i16,
+    /// Minutes after local midnight.
+    pub start_minute: i32,
+    pub duration_minutes: i32,
+    pub timezone: String,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub id: String,
+    /// `None` takes the station off the regular schedule for the period.
+    pub show_id: Option<String>,
+    pub starts_at: i64,
+    pub ends_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Airing {
+    pub show_id: String,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    pub slot_id: Option<String>,
+    pub override_id: Option<String>,
+}
+
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>().map_err(|_| format!("Unknown time zone: {}", name))
+}
+
+/// Parses `HH:MM` into minutes after midnight.
+pub fn parse_start(value: &str) -> Result<i32, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map(|t| (t.signed_duration_since(NaiveTime::MIN).num_minutes()) as i32)
+        .map_err(|_| format!("Invalid start time: {}", value))
+}
+
+/// Resolves a local wall-clock time to an instant. During the autumn overlap the
+/// first occurrence wins; inside the spring gap the time moves forward by the
+/// length of the gap (02:30 becomes 03:30), the same way clocks do.
+pub fn resolve(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) => Some(t),
+        LocalResult::Ambiguous(first, _) => Some(first),
+        LocalResult::None => {
+            let before = tz.from_local_datetime(&(local - Duration::days(1))).earliest()?.offset().fix();
+            let utc = local - Duration::seconds(before.local_minus_utc() as i64);
+            Some(Utc.from_utc_datetime(&utc).with_timezone(&tz))
+        }
+    }
+}
+
+/// Start of the given local day as a Unix timestamp.
+pub fn day_start(tz: Tz, date: NaiveDate) -> Option<i64> {
+    resolve(tz, date.and_time(NaiveTime::MIN)).map(|t| t.timestamp())
+}
+
+fn local_date(tz: Tz, timestamp: i64) -> Option<NaiveDate> {
+    DateTime::from_timestamp(timestamp, 0).map(|t| t.with_timezone(&tz).date_naive())
+}
+
+/// Airings of a single slot overlapping `[from, to)`.
+fn expand_slot(slot: &Slot, from: i64, to: i64) -> Vec<Airing> {
+    let Ok(tz) = parse_timezone(&slot.timezone) else {
+        return Vec::new();
+    };
+    let (Some(first), Some(last)) = (local_date(tz, from), local_date(tz, to)) else {
+        return Vec::new();
+    };
+
+    let mut airings = Vec::new();
+    // Start back far enough to catch airings that began before `from` but are
+    // still on air, however long the slot runs; the spare days cover a partial
+    // day, the start minute and DST shifts.
+    let lookback = slot.duration_minutes as i64 / (24 * 60) + 2;
+    let mut date = first - Duration::days(lookback);
+    while date <= last {
+        if date.weekday().num_days_from_monday() as i16 == slot.weekday {
+            let local = date.and_time(NaiveTime::MIN) + Duration::minutes(slot.start_minute as i64);
+            if let Some(start) = resolve(tz, local) {
+                let starts_at = start.timestamp();
+                let ends_at = starts_at + slot.duration_minutes as i64 * 60;
+                let valid = slot.valid_from.is_none_or(|v| starts_at >= v)
+                    && slot.valid_until.is_none_or(|v| starts_at < v);
+                if valid && ends_at > from && starts_at < to {
+                    airings.push(Airing {
+                        show_id: slot.show_id.clone(),
+                        starts_at,
+                        ends_at,
+                        slot_id: Some(slot.id.clone()),
+                        override_id: None,
+                    });
+                }
+            }
+        }
+        date += Duration::days(1);
+    }
+    airings
+}
+
+/// Cuts the override period out of a recurring airing, keeping what is left on either side.
+fn cut(airing: Airing, o: &Override) -> Vec<Airing> {
+    if o.ends_at <= airing.starts_at || o.starts_at >= airing.ends_at {
+        return vec![airing];
+    }
+    let mut parts = Vec::new();
+    if airing.starts_at < o.starts_at {
+        parts.push(Airing { ends_at: o.starts_at, ..airing.clone() });
+    }
+    if airing.ends_at > o.ends_at {
+        parts.push(Airing { starts_at: o.ends_at, ..airing });
+    }
+    parts
+}
+
+/// Every airing overlapping `[from, to)`, ordered by start time.
+pub fn expand(slots: &[Slot], overrides: &[Override], from: i64, to: i64) -> Vec<Airing> {
+    let mut airings: Vec<Airing> = slots.iter().flat_map(|slot| expand_slot(slot, from, to)).collect();
+
+    for o in overrides {
+        airings = airings.into_iter().flat_map(|a| cut(a, o)).collect();
+    }
+
+    airings.extend(
+        overrides
+            .iter()
+            .filter(|o| o.ends_at > from && o.starts_at < to)
+            .filter_map(|o| {
+                o.show_id.as_ref().map(|show_id| Airing {
+                    show_id: show_id.clone(),
+                    starts_at: o.starts_at,
+                    ends_at: o.ends_at,
+                    slot_id: None,
+                    override_id: Some(o.id.clone()),
+                })
+            }),
+    );
+
+    airings.sort_by_key(|a| (a.starts_at, a.ends_at));
+    airings
+}
+
+/// Loads slots and overrides from the database and expands them for `[from, to)`.
+pub async fn load(pool: &PgPool, from: i64, to: i64) -> Result<Vec<Airing>, String> {
+    let slots = sqlx::query_as!(
+        Slot,
+        r#"
+        SELECT id, show_id, weekday, start_minute, duration_minutes, timezone, valid_from, valid_until
+        FROM public.schedule_slots
+        WHERE (valid_from IS NULL OR valid_from < $2)
+          AND (valid_until IS NULL OR valid_until > $1)
+        "#,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Schedule lookup failed: {}", e))?;
+
+    let overrides = sqlx::query_as!(
+        Override,
+        r#"
+        SELECT id, show_id, starts_at, ends_at
+        FROM public.schedule_overrides
+        WHERE ends_at > $1 AND starts_at < $2
+        ORDER BY created_at
+        "#,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Schedule lookup failed: {}", e))?;
+
+    Ok(expand(&slots, &overrides, from, to))
+}
+
+/// The airing on at `at`, if any.
+pub async fn on_air(pool: &PgPool, at: i64) -> Result<Option<Airing>, String> {
+    Ok(load(pool, at, at + 1).await?.into_iter().find(|a| a.starts_at <= at && at < a.ends_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BERLIN: Tz = chrono_tz::Europe::Berlin;
+
+    fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> i64 {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap().timestamp()
+    }
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d).unwrap().and_hms_opt(h, mi, 0).unwrap()
+    }
+
+    fn slot(weekday: i16, start: &str, duration_minutes: i32) -> Slot {
+        Slot {
+            id: "slot".into(),
+            show_id: "show".into(),
+            weekday,
+            start_minute: parse_start(start).unwrap(),
+            duration_minutes,
+            timezone: "Europe/Berlin".into(),
+            valid_from: None,
+            valid_until: None,
+        }
+    }
+
+    fn starts(airings: &[Airing]) -> Vec<i64> {
+        airings.iter().map(|a| a.starts_at).collect()
+    }
+
+    #[test]
+    fn slots_longer_than_a_day_stay_on_air() {
+        // Monday 2024-01-01 18:00 CET for three days.
+        let marathon = slot(0, "18:00", 3 * 24 * 60);
+        let thursday = utc(2024, 1, 4, 12, 0);
+        let airings = expand_slot(&marathon, thursday, thursday + 60);
+        assert_eq!(starts(&airings), vec![utc(2024, 1, 1, 17, 0)]);
+        assert_eq!(airings[0].ends_at, utc(2024, 1, 4, 17, 0));
+    }
+
+    #[test]
+    fn resolves_times_inside_the_spring_gap_forward() {
+        // Clocks jump from 02:00 CET to 03:00 CEST on 31 March 2024.
+        let t = resolve(BERLIN, local(2024, 3, 31, 2, 30)).unwrap();
+        assert_eq!(t.timestamp(), utc(2024, 3, 31, 1, 30));
+        assert_eq!(t.naive_local(), local(2024, 3, 31, 3, 30));
+    }
+
+    #[test]
+    fn resolves_times_inside_the_autumn_overlap_to_the_first() {
+        // 02:00-03:00 happens twice on 27 October 2024; the CEST one comes first.
+        let t = resolve(BERLIN, local(2024, 10, 27, 2, 30)).unwrap();
+        assert_eq!(t.timestamp(), utc(2024, 10, 27, 0, 30));
+    }
+
+    #[test]
+    fn day_start_follows_the_local_offset() {
+        let date = |d| NaiveDate::from_ymd_opt(2024, 3, d).unwrap();
+        assert_eq!(day_start(BERLIN, date(30)), Some(utc(2024, 3, 29, 23, 0)));
+        assert_eq!(day_start(BERLIN, date(31)), Some(utc(2024, 3, 30, 23, 0)));
+        assert_eq!(day_start(BERLIN, NaiveDate::from_ymd_opt(2024, 4, 1).unwrap()), Some(utc(2024, 3, 31, 22, 0)));
+    }
+
+    #[test]
+    fn weekly_slots_keep_their_wall_clock_time_across_dst() {
+        let sunday_evening = [slot(6, "20:00", 60)];
+
+        let spring = expand(&sunday_evening, &[], utc(2024, 3, 20, 0, 0), utc(2024, 4, 3, 0, 0));
+        assert_eq!(starts(&spring), vec![utc(2024, 3, 24, 19, 0), utc(2024, 3, 31, 18, 0)]);
+
+        let autumn = expand(&sunday_evening, &[], utc(2024, 10, 23, 0, 0), utc(2024, 11, 6, 0, 0));
+        assert_eq!(starts(&autumn), vec![utc(2024, 10, 27, 19, 0), utc(2024, 11, 3, 19, 0)]);
+    }
+
+    #[test]
+    fn slots_in_the_gap_or_overlap_air_once() {
+        let spring = expand(&[slot(6, "02:30", 30)], &[], utc(2024, 3, 30, 0, 0), utc(2024, 4, 1, 0, 0));
+        assert_eq!(starts(&spring), vec![utc(2024, 3, 31, 1, 30)]);
+
+        let autumn = expand(&[slot(6, "02:30", 30)], &[], utc(2024, 10, 26, 0, 0), utc(2024, 10, 28, 0, 0));
+        assert_eq!(starts(&autumn), vec![utc(2024, 10, 27, 0, 30)]);
+    }
+
+    #[test]
+    fn overnight_slots_last_their_real_duration_across_a_transition() {
+        // Saturday 23:00 for four hours runs over the spring change: it ends at 04:00 CEST.
+        let airings = expand(&[slot(5, "23:00", 240)], &[], utc(2024, 3, 30, 0, 0), utc(2024, 3, 31, 6, 0));
+        assert_eq!(airings.len(), 1);
+        assert_eq!((airings[0].starts_at, airings[0].ends_at), (utc(2024, 3, 30, 22, 0), utc(2024, 3, 31, 2, 0)));
+
+        // A window starting mid-show still returns the airing that is on air.
+        let airings = expand(&[slot(5, "23:00", 240)], &[], utc(2024, 3, 31, 1, 0), utc(2024, 3, 31, 1, 30));
+        assert_eq!(starts(&airings), vec![utc(2024, 3, 30, 22, 0)]);
+    }
+
+    #[test]
+    fn overrides_cut_airings_on_dst_days() {
+        let special = Override {
+            id: "special".into(),
+            show_id: Some("special-show".into()),
+            starts_at: utc(2024, 3, 31, 18, 15),
+            ends_at: utc(2024, 3, 31, 18, 45),
+        };
+        let airings = expand(&[slot(6, "20:00", 60)], &[special], utc(2024, 3, 31, 0, 0), utc(2024, 4, 1, 0, 0));
+        let spans: Vec<_> = airings.iter().map(|a| (a.show_id.as_str(), a.starts_at, a.ends_at)).collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("show", utc(2024, 3, 31, 18, 0), utc(2024, 3, 31, 18, 15)),
+                ("special-show", utc(2024, 3, 31, 18, 15), utc(2024, 3, 31, 18, 45)),
+                ("show", utc(2024, 3, 31, 18, 45), utc(2024, 3, 31, 19, 0)),
+            ]
+        );
+    }
+}
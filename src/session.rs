@@ -0,0 +1,731 @@
+//! Engine-issued sessions.
+//!
+//! Signing in creates a row in `public.sessions` and hands the client two
+//! things: a short-lived HS256 access token to send as `Authorization: Bearer`,
+//! and an HttpOnly refresh cookie (`<session id>.<secret>`) that `POST /refresh`
+//! exchanges for a new access token. Only a SHA-256 of the secret is stored.
+//!
+//! Every refresh rotates the secret. Secrets a session has moved past are kept
+//! in `public.session_retired_tokens`; if one is presented again the cookie has
+//! been copied, and the session is revoked for both holders. The one exception
+//! is the secret rotated away from in the last few seconds: two tabs refreshing
+//! at once both send it, so the later one gets an access token and keeps the
+//! cookie the earlier one received. Retired secrets are dropped once their
+//! session expires or is revoked.
+//!
+//! Settings:
+//! - `SESSION_SECRET`: HMAC key for access tokens, required
+//! - `ACCESS_TOKEN_TTL_SECONDS`: default 900
+//! - `REFRESH_TOKEN_TTL_SECONDS`: default 30 days
+//! - `REFRESH_REUSE_GRACE_SECONDS`: how long the previous secret still refreshes, default 10
+//! - `SESSION_COOKIE_SECURE`: default `true`; set `false` for plain-http development
+//! - `SESSION_COOKIE_SAMESITE`: `Strict`, `Lax` (default) or `None`
+//! - `SESSION_COOKIE_DOMAIN`: optional cookie domain
+//! - `TRUSTED_PROXIES`: comma-separated proxy addresses or CIDR ranges (e.g.
+//!   `10.0.0.0/8`) whose `X-Forwarded-For` is believed; otherwise the peer address is recorded
+
+use std::convert::Infallible;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use chrono::Utc;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::api_key;
+use crate::authz::Role;
+use crate::media::fetch::content_hash;
+
+pub const REFRESH_COOKIE: &str = "engine_refresh";
+const ISSUER: &str = "engine";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// `public.users.id`.
+    pub sub: String,
+    /// `public.sessions.id`.
+    pub sid: String,
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+pub struct AccessToken {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+fn setting(name: &str, default: i64) -> i64 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[cfg(not(test))]
+fn secret() -> Result<String, String> {
+    env::var("SESSION_SECRET").map_err(|_| "Missing SESSION_SECRET".to_string())
+}
+
+/// Tests sign with a fixed key rather than mutating the process environment.
+#[cfg(test)]
+fn secret() -> Result<String, String> {
+    Ok(env::var("SESSION_SECRET").unwrap_or_else(|_| "test-session-secret".to_string()))
+}
+
+pub fn access_token(user_id: &str, session_id: &str) -> Result<AccessToken, String> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        sid: session_id.to_string(),
+        iss: ISSUER.to_string(),
+        iat: now,
+        exp: now + setting("ACCESS_TOKEN_TTL_SECONDS", 900),
+    };
+    let token = encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret()?.as_bytes()))
+        .map_err(|e| format!("Token signing failed: {}", e))?;
+
+    Ok(AccessToken { token, expires_at: claims.exp })
+}
+
+pub fn verify_access(token: &str) -> Result<Claims, String> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[ISSUER]);
+    validation.leeway = 0;
+
+    decode::<Claims>(token, &DecodingKey::from_secret(secret()?.as_bytes()), &validation)
+        .map(|data| data.claims)
+        .map_err(|e| format!("Invalid access token: {}", e))
+}
+
+fn refresh_cookie(value: &str, max_age: i64) -> String {
+    let secure = env::var("SESSION_COOKIE_SECURE").map(|v| v != "false").unwrap_or(true);
+    let same_site = env::var("SESSION_COOKIE_SAMESITE").unwrap_or_else(|_| "Lax".to_string());
+
+    let mut cookie = format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite={}", REFRESH_COOKIE, value, max_age, same_site);
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    if let Ok(domain) = env::var("SESSION_COOKIE_DOMAIN") {
+        cookie.push_str("; Domain=");
+        cookie.push_str(&domain);
+    }
+    cookie
+}
+
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Client details recorded with a session.
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// An address or CIDR range from `TRUSTED_PROXIES`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProxyRange {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl ProxyRange {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let bad = || format!("Invalid proxy address: {}", value);
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u32>().map_err(|_| bad())?)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| bad())?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        if prefix > bits {
+            return Err(bad());
+        }
+        Ok(ProxyRange { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let shift = |bits: u32| bits - self.prefix;
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                u64::from(range.to_bits()) >> shift(32) == u64::from(ip.to_bits()) >> shift(32)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                range.to_bits().checked_shr(shift(128)).unwrap_or(0) == ip.to_bits().checked_shr(shift(128)).unwrap_or(0)
+            }
+            _ => false,
+        }
+    }
+}
+
+static TRUSTED_PROXIES: Lazy<Vec<ProxyRange>> = Lazy::new(|| {
+    env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .filter_map(|v| ProxyRange::parse(v).inspect_err(|e| tracing::warn!("{}", e)).ok())
+        .collect()
+});
+
+/// The client's address: the peer itself unless it is a trusted proxy, in which
+/// case the nearest `X-Forwarded-For` hop that is not a trusted proxy.
+fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted: &[ProxyRange]) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    let peer = peer?.to_canonical();
+    if !is_trusted(peer) {
+        return Some(peer);
+    }
+
+    let header = |name: &str| headers.get_all(name).iter().filter_map(|v| v.to_str().ok()).collect::<Vec<_>>().join(",");
+    // Each proxy appends the address it received the request from, so walk
+    // back from the right past our own proxies; hops further left are unverified.
+    let mut client = None;
+    for hop in header("x-forwarded-for").rsplit(',').map(str::trim).filter(|h| !h.is_empty()) {
+        let Ok(ip) = hop.parse::<IpAddr>() else {
+            break;
+        };
+        client = Some(ip.to_canonical());
+        if !is_trusted(ip.to_canonical()) {
+            break;
+        }
+    }
+
+    client
+        .or_else(|| header("x-real-ip").trim().parse::<IpAddr>().ok().map(|ip| ip.to_canonical()))
+        .or(Some(peer))
+}
+
+impl Device {
+    pub fn new(headers: &HeaderMap, peer: Option<IpAddr>) -> Self {
+        Device {
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            ip: client_ip(headers, peer, &TRUSTED_PROXIES).map(|ip| ip.to_string()),
+        }
+    }
+}
+
+/// Reads the peer address from `ConnectInfo`, so the server must be started
+/// with `into_make_service_with_connect_info`.
+impl<S: Send + Sync> FromRequestParts<S> for Device {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+        Ok(Device::new(&parts.headers, peer))
+    }
+}
+
+fn new_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// `Set-Cookie` value that removes the refresh cookie.
+pub fn clear_cookie() -> String {
+    refresh_cookie("", 0)
+}
+
+/// Opens a session for `user_id`, returning an access token and the `Set-Cookie` value.
+pub async fn create(pool: &PgPool, user_id: &str, device: &Device) -> Result<(AccessToken, String), String> {
+    let now = Utc::now().timestamp();
+    let ttl = setting("REFRESH_TOKEN_TTL_SECONDS", 30 * 24 * 3600);
+    let id = Uuid::new_v4().to_string();
+    let secret = new_secret();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO public.sessions (id, user_id, refresh_hash, user_agent, ip, created_at, last_used_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6, $7)
+        "#,
+        id,
+        user_id,
+        content_hash(secret.as_bytes()),
+        device.user_agent,
+        device.ip,
+        now,
+        now + ttl,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Session insert failed: {}", e))?;
+
+    prune_retired(pool, now).await?;
+
+    let access = access_token(user_id, &id)?;
+    Ok((access, refresh_cookie(&format!("{}.{}", id, secret), ttl)))
+}
+
+/// Forgets retired secrets of sessions that can no longer be refreshed anyway.
+async fn prune_retired(pool: &PgPool, now: i64) -> Result<u64, String> {
+    sqlx::query!(
+        r#"
+        DELETE FROM public.session_retired_tokens t
+        USING public.sessions s
+        WHERE s.id = t.session_id
+          AND (s.expires_at <= $1 OR s.revoked_at IS NOT NULL)
+        "#,
+        now
+    )
+    .execute(pool)
+    .await
+    .map(|done| done.rows_affected())
+    .map_err(|e| format!("Retired token cleanup failed: {}", e))
+}
+
+/// Exchanges a refresh cookie for a new access token and a rotated cookie;
+/// `None` if the session is unknown, expired or revoked, or the cookie was reused.
+/// A refresh that lost a race with another one gets no cookie, since the
+/// winner's is already on its way to the client.
+pub async fn refresh(
+    pool: &PgPool,
+    cookie: &str,
+    device: &Device,
+) -> Result<Option<(AccessToken, Option<String>)>, String> {
+    let Some((id, secret)) = cookie.split_once('.') else {
+        return Ok(None);
+    };
+    let db = |e: sqlx::Error| format!("Session refresh failed: {}", e);
+    let now = Utc::now().timestamp();
+    let presented = content_hash(secret.as_bytes());
+
+    let mut tx = pool.begin().await.map_err(db)?;
+
+    let Some(session) = sqlx::query!(
+        r#"
+        SELECT user_id, refresh_hash, previous_hash, rotated_at, expires_at, revoked_at FROM public.sessions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db)?
+    else {
+        return Ok(None);
+    };
+
+    let live = session.revoked_at.is_none() && session.expires_at > now;
+
+    if session.refresh_hash != presented {
+        let grace = setting("REFRESH_REUSE_GRACE_SECONDS", 10);
+        if live
+            && session.previous_hash.as_deref() == Some(presented.as_str())
+            && session.rotated_at.is_some_and(|at| now - at <= grace)
+        {
+            return Ok(Some((access_token(&session.user_id, id)?, None)));
+        }
+
+        let reused = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM public.session_retired_tokens WHERE session_id = $1 AND refresh_hash = $2
+            ) AS "reused!"
+            "#,
+            id,
+            presented,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db)?;
+
+        if reused && session.revoked_at.is_none() {
+            tracing::warn!("Refresh token reuse on session {}; revoking it", id);
+            sqlx::query!(
+                r#"
+                UPDATE public.sessions SET revoked_at = $2, revoked_reason = 'reuse'
+                WHERE id = $1
+                "#,
+                id,
+                now,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(db)?;
+            tx.commit().await.map_err(db)?;
+        }
+        return Ok(None);
+    }
+
+    if !live {
+        return Ok(None);
+    }
+
+    let secret = new_secret();
+    sqlx::query!(
+        r#"
+        INSERT INTO public.session_retired_tokens (refresh_hash, session_id, retired_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        presented,
+        id,
+        now,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE public.sessions
+        SET refresh_hash = $2, previous_hash = refresh_hash, rotated_at = $3, last_used_at = $3,
+            user_agent = coalesce($4, user_agent), ip = coalesce($5, ip)
+        WHERE id = $1
+        "#,
+        id,
+        content_hash(secret.as_bytes()),
+        now,
+        device.user_agent,
+        device.ip,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db)?;
+
+    tx.commit().await.map_err(db)?;
+
+    let access = access_token(&session.user_id, id)?;
+    Ok(Some((access, Some(refresh_cookie(&format!("{}.{}", id, secret), session.expires_at - now)))))
+}
+
+/// Revokes the session a refresh cookie belongs to, if the cookie is current.
+pub async fn end(pool: &PgPool, cookie: &str) -> Result<bool, String> {
+    let Some((id, secret)) = cookie.split_once('.') else {
+        return Ok(false);
+    };
+
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE public.sessions SET revoked_at = $3, revoked_reason = 'logout'
+        WHERE id = $1 AND refresh_hash = $2 AND revoked_at IS NULL
+        "#,
+        id,
+        content_hash(secret.as_bytes()),
+        Utc::now().timestamp(),
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Logout failed: {}", e))?;
+
+    Ok(revoked.rows_affected() > 0)
+}
+
+/// Revokes one session of `user_id`; `false` if there is no such live session.
+pub async fn revoke(pool: &PgPool, user_id: &str, session_id: &str, reason: &str) -> Result<bool, String> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE public.sessions SET revoked_at = $3, revoked_reason = $4
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id,
+        Utc::now().timestamp(),
+        reason,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Revoke failed: {}", e))?;
+
+    Ok(revoked.rows_affected() > 0)
+}
+
+/// Revokes every live session of `user_id`, returning how many there were.
+pub async fn revoke_all(pool: &PgPool, user_id: &str, reason: &str) -> Result<u64, String> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE public.sessions SET revoked_at = $2, revoked_reason = $3
+        WHERE user_id = $1 AND revoked_at IS NULL
+        "#,
+        user_id,
+        Utc::now().timestamp(),
+        reason,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Revoke failed: {}", e))?;
+
+    Ok(revoked.rows_affected())
+}
+
+/// How a request proved who it is.
+#[derive(Debug, Clone)]
+pub enum Credential {
+    /// An access token for this `public.sessions` row.
+    Session(String),
+    /// An API key, limited to routes its scopes open.
+    ApiKey { id: String, scopes: Vec<String> },
+}
+
+/// The caller, taken from the `Authorization: Bearer` access token or API key.
+/// Sessions must still be live and the role is read fresh, so revoking a
+/// session or changing a role takes effect immediately.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: String,
+    pub role: Role,
+    pub credential: Credential,
+}
+
+impl AuthUser {
+    pub fn session_id(&self) -> Option<&str> {
+        match &self.credential {
+            Credential::Session(id) => Some(id),
+            Credential::ApiKey { .. } => None,
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            return Ok(user.clone());
+        }
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or((StatusCode::UNAUTHORIZED, "Missing bearer token".to_string()))?;
+
+        let pool = parts
+            .extensions
+            .get::<PgPool>()
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Database pool missing".to_string()))?;
+
+        if token.starts_with(api_key::KEY_PREFIX) {
+            let key = api_key::authenticate(pool, token)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+                .ok_or((StatusCode::UNAUTHORIZED, "Invalid, expired or revoked API key".to_string()))?;
+
+            return Ok(AuthUser {
+                user_id: key.user_id,
+                role: Role::parse(&key.role).unwrap_or(Role::Reader),
+                credential: Credential::ApiKey { id: key.id, scopes: key.scopes },
+            });
+        }
+
+        let claims = verify_access(token).map_err(|e| (StatusCode::UNAUTHORIZED, e))?;
+
+        let role = sqlx::query_scalar!(
+            r#"
+            SELECT u.role FROM public.sessions s
+            JOIN public.users u ON u.id = s.user_id
+            WHERE s.id = $1 AND s.user_id = $2 AND s.revoked_at IS NULL AND s.expires_at > $3
+            "#,
+            claims.sid,
+            claims.sub,
+            Utc::now().timestamp(),
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Session lookup failed: {}", e)))?
+        .ok_or((StatusCode::UNAUTHORIZED, "Session expired or revoked".to_string()))?;
+
+        Ok(AuthUser {
+            user_id: claims.sub,
+            role: Role::parse(&role).unwrap_or(Role::Reader),
+            credential: Credential::Session(claims.sid),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use crate::db::test_pool;
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_from_untrusted_peers() {
+        let forged = headers(&[("x-forwarded-for", "1.2.3.4"), ("x-real-ip", "5.6.7.8")]);
+        assert_eq!(client_ip(&forged, ip("203.0.113.9"), &[]), ip("203.0.113.9"));
+
+        let trusted = [ProxyRange::parse("10.0.0.0/8").unwrap()];
+        assert_eq!(client_ip(&forged, ip("203.0.113.9"), &trusted), ip("203.0.113.9"));
+        assert_eq!(client_ip(&forged, None, &trusted), None);
+    }
+
+    #[test]
+    fn takes_the_nearest_untrusted_hop_behind_trusted_proxies() {
+        let trusted = [ProxyRange::parse("10.0.0.0/8").unwrap(), ProxyRange::parse("192.0.2.1").unwrap()];
+
+        // The client prepended a forged hop; only the right-hand entries come from our proxies.
+        let chain = headers(&[("x-forwarded-for", "6.6.6.6, 198.51.100.7, 10.1.2.3")]);
+        assert_eq!(client_ip(&chain, ip("192.0.2.1"), &trusted), ip("198.51.100.7"));
+
+        // Repeated headers are one list.
+        let split = headers(&[("x-forwarded-for", "198.51.100.7"), ("x-forwarded-for", "10.1.2.3")]);
+        assert_eq!(client_ip(&split, ip("10.0.0.1"), &trusted), ip("198.51.100.7"));
+
+        let real_ip = headers(&[("x-real-ip", "198.51.100.8")]);
+        assert_eq!(client_ip(&real_ip, ip("10.0.0.1"), &trusted), ip("198.51.100.8"));
+
+        assert_eq!(client_ip(&HeaderMap::new(), ip("10.0.0.1"), &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn matches_proxy_ranges() {
+        let range = ProxyRange::parse("10.0.0.0/8").unwrap();
+        assert!(range.contains("10.255.0.1".parse().unwrap()));
+        assert!(range.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+
+        let v6 = ProxyRange::parse("2001:db8::/32").unwrap();
+        assert!(v6.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains("2001:db9::1".parse().unwrap()));
+
+        assert!(ProxyRange::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!(ProxyRange::parse("10.0.0.0/33").is_err());
+        assert!(ProxyRange::parse("proxy.local").is_err());
+    }
+
+    const DEVICE: Device = Device { user_agent: None, ip: None };
+
+    fn cookie_value(set_cookie: &str) -> String {
+        let pair = set_cookie.split(';').next().unwrap();
+        pair.strip_prefix(&format!("{}=", REFRESH_COOKIE)).unwrap().to_string()
+    }
+
+    async fn signed_in(pool: &PgPool) -> (String, String) {
+        let user_id = sqlx::query_scalar!("INSERT INTO public.users DEFAULT VALUES RETURNING id")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let (_, set_cookie) = create(pool, &user_id, &DEVICE).await.unwrap();
+        (user_id, cookie_value(&set_cookie))
+    }
+
+    async fn rotate(pool: &PgPool, cookie: &str) -> String {
+        let (_, set_cookie) = refresh(pool, cookie, &DEVICE).await.unwrap().expect("refresh accepted");
+        cookie_value(&set_cookie.expect("cookie rotated"))
+    }
+
+    async fn revoked_reason(pool: &PgPool, cookie: &str) -> Option<String> {
+        let id = cookie.split_once('.').unwrap().0;
+        sqlx::query_scalar!("SELECT revoked_reason FROM public.sessions WHERE id = $1", id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn delete_user(pool: &PgPool, user_id: &str) {
+        sqlx::query!("DELETE FROM public.users WHERE id = $1", user_id).execute(pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotates_the_secret_on_every_refresh() {
+        let pool = test_pool().await;
+        let (user_id, first) = signed_in(&pool).await;
+
+        let second = rotate(&pool, &first).await;
+        let third = rotate(&pool, &second).await;
+        assert_eq!(first.split_once('.').unwrap().0, third.split_once('.').unwrap().0);
+        assert_ne!(second, third);
+
+        let (access, _) = refresh(&pool, &third, &DEVICE).await.unwrap().unwrap();
+        assert_eq!(verify_access(&access.token).unwrap().sub, user_id);
+        assert!(refresh(&pool, "unknown.secret", &DEVICE).await.unwrap().is_none());
+        assert!(refresh(&pool, "no-separator", &DEVICE).await.unwrap().is_none());
+
+        delete_user(&pool, &user_id).await;
+    }
+
+    #[tokio::test]
+    async fn lets_a_concurrent_refresh_through_without_rotating() {
+        let pool = test_pool().await;
+        let (user_id, first) = signed_in(&pool).await;
+        let second = rotate(&pool, &first).await;
+
+        // The other tab still sends the first secret.
+        let (access, cookie) = refresh(&pool, &first, &DEVICE).await.unwrap().expect("within grace");
+        assert!(cookie.is_none());
+        assert_eq!(verify_access(&access.token).unwrap().sub, user_id);
+        assert_eq!(revoked_reason(&pool, &first).await, None);
+        rotate(&pool, &second).await;
+
+        delete_user(&pool, &user_id).await;
+    }
+
+    #[tokio::test]
+    async fn revokes_the_session_when_a_retired_secret_is_reused() {
+        let pool = test_pool().await;
+
+        // Past the grace window.
+        let (user_id, first) = signed_in(&pool).await;
+        let second = rotate(&pool, &first).await;
+        sqlx::query!(
+            "UPDATE public.sessions SET rotated_at = rotated_at - 60 WHERE id = $1",
+            first.split_once('.').unwrap().0
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(refresh(&pool, &first, &DEVICE).await.unwrap().is_none());
+        assert_eq!(revoked_reason(&pool, &first).await.as_deref(), Some("reuse"));
+        assert!(refresh(&pool, &second, &DEVICE).await.unwrap().is_none());
+        delete_user(&pool, &user_id).await;
+
+        // Only the immediately previous secret gets the grace window.
+        let (user_id, first) = signed_in(&pool).await;
+        let second = rotate(&pool, &first).await;
+        let third = rotate(&pool, &second).await;
+        assert!(refresh(&pool, &first, &DEVICE).await.unwrap().is_none());
+        assert_eq!(revoked_reason(&pool, &first).await.as_deref(), Some("reuse"));
+        assert!(refresh(&pool, &third, &DEVICE).await.unwrap().is_none());
+        delete_user(&pool, &user_id).await;
+    }
+
+    #[tokio::test]
+    async fn prunes_retired_secrets_of_expired_sessions() {
+        let pool = test_pool().await;
+        let (user_id, first) = signed_in(&pool).await;
+        rotate(&pool, &first).await;
+        let id = first.split_once('.').unwrap().0;
+
+        let retired = || sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM public.session_retired_tokens WHERE session_id = $1"#, id);
+        assert_eq!(retired().fetch_one(&pool).await.unwrap(), 1);
+
+        sqlx::query!("UPDATE public.sessions SET expires_at = 0 WHERE id = $1", id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        prune_retired(&pool, Utc::now().timestamp()).await.unwrap();
+        assert_eq!(retired().fetch_one(&pool).await.unwrap(), 0);
+
+        delete_user(&pool, &user_id).await;
+    }
+}
@@ -0,0 +1,437 @@
+//! Now-playing ingestion from the stream server.
+//!
+//! When `STREAM_STATUS_URL` is set, a background task polls Icecast's
+//! `status-json.xsl` or Shoutcast v2's `/stats?json=1` and records track
+//! changes in `now_playing` / `play_history`, publishing them on the event bus.
+//!
+//! Settings:
+//! - `STREAM_SERVER`: `icecast` (default) or `shoutcast`
+//! - `STREAM_MOUNT`: Icecast mount to follow, e.g. `/live`; the first source otherwise
+//! - `STREAM_POLL_SECONDS`: poll interval, default 10
+
+use std::env;
+use std::time::Duration;
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::Value;
+use sqlx::PgPool;
+use crate::events::{Event, EventBus, Listeners, NowPlaying};
+use crate::schedule;
+
+const DEFAULT_POLL_SECONDS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerKind {
+    Icecast,
+    Shoutcast,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamStatus {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub listeners: Option<i32>,
+}
+
+fn text(value: &Value) -> Option<String> {
+    value.as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string)
+}
+
+/// Stream servers usually only know "Artist - Title".
+fn split_title(raw: Option<String>) -> (Option<String>, Option<String>) {
+    match raw {
+        Some(raw) => match raw.split_once(" - ") {
+            Some((artist, title)) => (Some(artist.trim().to_string()), Some(title.trim().to_string())),
+            None => (None, Some(raw)),
+        },
+        None => (None, None),
+    }
+}
+
+/// Reads one source out of Icecast's `status-json.xsl`. `source` is an object
+/// with a single mount and an array with several; a missing source means off air.
+pub fn parse_icecast(body: &Value, mount: Option<&str>) -> Result<StreamStatus, String> {
+    let stats = body.get("icestats").ok_or("Missing icestats")?;
+    let sources: Vec<&Value> = match stats.get("source") {
+        Some(Value::Array(sources)) => sources.iter().collect(),
+        Some(source @ Value::Object(_)) => vec![source],
+        _ => Vec::new(),
+    };
+
+    let source = match mount {
+        Some(mount) => sources.into_iter().find(|s| {
+            s.get("listenurl").and_then(Value::as_str).is_some_and(|url| url.ends_with(mount))
+        }),
+        None => sources.into_iter().next(),
+    };
+
+    let Some(source) = source else {
+        return Ok(StreamStatus { title: None, artist: None, listeners: Some(0) });
+    };
+
+    let listeners = source.get("listeners").and_then(Value::as_i64).map(|n| n as i32);
+    let title = source.get("title").and_then(text).or_else(|| source.get("yp_currently_playing").and_then(text));
+    let (artist, title) = match source.get("artist").and_then(text) {
+        Some(artist) => (Some(artist), title),
+        None => split_title(title),
+    };
+
+    Ok(StreamStatus { title, artist, listeners })
+}
+
+/// Reads Shoutcast v2's JSON stats.
+pub fn parse_shoutcast(body: &Value) -> Result<StreamStatus, String> {
+    let listeners = body
+        .get("currentlisteners")
+        .and_then(Value::as_i64)
+        .ok_or("Missing currentlisteners")? as i32;
+    let online = body.get("streamstatus").and_then(Value::as_i64).unwrap_or(1) != 0;
+    let (artist, title) = if online { split_title(body.get("songtitle").and_then(text)) } else { (None, None) };
+
+    Ok(StreamStatus { title, artist, listeners: Some(listeners) })
+}
+
+/// Records the current track. A new `play_history` entry is opened and a
+/// `now_playing` event published only when the track actually changed.
+pub async fn record_track(
+    pool: &PgPool,
+    bus: &EventBus,
+    title: Option<String>,
+    artist: Option<String>,
+    listeners: Option<i32>,
+) -> Result<NowPlaying, String> {
+    let now = Utc::now().timestamp();
+    let show_id = schedule::on_air(pool, now).await?.map(|a| a.show_id);
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    let current = sqlx::query!(
+        r#"
+        SELECT title, artist, show_id, listeners, started_at
+        FROM public.now_playing
+        WHERE id = 1
+        FOR UPDATE
+        "#
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(current) = current
+        && current.title == title
+        && current.artist == artist
+    {
+        return Ok(NowPlaying {
+            title,
+            artist,
+            show_id: current.show_id,
+            listeners: current.listeners,
+            started_at: current.started_at,
+        });
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE public.play_history SET ended_at = $1
+        WHERE ended_at IS NULL
+        "#,
+        now
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if title.is_some() || artist.is_some() {
+        sqlx::query!(
+            r#"
+            INSERT INTO public.play_history (title, artist, show_id, started_at, peak_listeners)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            title,
+            artist,
+            show_id,
+            now,
+            listeners,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO public.now_playing (id, title, artist, show_id, listeners, started_at, updated_at)
+        VALUES (1, $1, $2, $3, $4, $5, $5)
+        ON CONFLICT (id) DO UPDATE
+        SET title = EXCLUDED.title,
+            artist = EXCLUDED.artist,
+            show_id = EXCLUDED.show_id,
+            listeners = coalesce(EXCLUDED.listeners, now_playing.listeners),
+            started_at = EXCLUDED.started_at,
+            updated_at = EXCLUDED.updated_at
+        RETURNING listeners
+        "#,
+        title,
+        artist,
+        show_id,
+        listeners,
+        now,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    let now_playing = NowPlaying { title, artist, show_id, listeners: row.listeners, started_at: now };
+    bus.publish(Event::NowPlaying(now_playing.clone()));
+    Ok(now_playing)
+}
+
+/// Records the listener count, publishing a `listeners` event when it changed.
+pub async fn record_listeners(pool: &PgPool, bus: &EventBus, listeners: i32) -> Result<(), String> {
+    let changed = sqlx::query!(
+        r#"
+        UPDATE public.now_playing SET listeners = $1, updated_at = $2
+        WHERE id = 1 AND listeners IS DISTINCT FROM $1
+        "#,
+        listeners,
+        Utc::now().timestamp(),
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?
+    .rows_affected()
+        > 0;
+
+    if changed {
+        sqlx::query!(
+            r#"
+            UPDATE public.play_history SET peak_listeners = GREATEST(coalesce(peak_listeners, 0), $1)
+            WHERE ended_at IS NULL
+            "#,
+            listeners
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        bus.publish(Event::Listeners(Listeners { listeners }));
+    }
+    Ok(())
+}
+
+/// Seeds the bus with the last recorded track so listeners connecting after a restart see it.
+pub async fn restore(pool: &PgPool, bus: &EventBus) -> Result<(), String> {
+    let row = sqlx::query_as!(
+        NowPlaying,
+        r#"
+        SELECT title, artist, show_id, listeners, started_at
+        FROM public.now_playing
+        WHERE id = 1
+        "#
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(now_playing) = row {
+        bus.publish(Event::NowPlaying(now_playing));
+    }
+    Ok(())
+}
+
+async fn poll(client: &Client, url: &str, kind: ServerKind, mount: Option<&str>) -> Result<StreamStatus, String> {
+    let body: Value = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Stream status request failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Stream status is not JSON: {}", e))?;
+
+    match kind {
+        ServerKind::Icecast => parse_icecast(&body, mount),
+        ServerKind::Shoutcast => parse_shoutcast(&body),
+    }
+}
+
+/// Starts the poller if `STREAM_STATUS_URL` is configured.
+pub fn spawn_poller(pool: PgPool, bus: EventBus) {
+    let Ok(url) = env::var("STREAM_STATUS_URL") else {
+        return;
+    };
+    let kind = match env::var("STREAM_SERVER").as_deref() {
+        Ok("shoutcast") => ServerKind::Shoutcast,
+        _ => ServerKind::Icecast,
+    };
+    let mount = env::var("STREAM_MOUNT").ok();
+    let interval = env::var("STREAM_POLL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_POLL_SECONDS)
+        .max(1);
+
+    tokio::spawn(run_poller(pool, bus, url, kind, mount, Duration::from_secs(interval)));
+}
+
+async fn run_poller(
+    pool: PgPool,
+    bus: EventBus,
+    url: String,
+    kind: ServerKind,
+    mount: Option<String>,
+    interval: Duration,
+) {
+    let client = match Client::builder().timeout(interval.max(Duration::from_secs(2))).build() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Stream poller disabled: {}", e);
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+        let status = match poll(&client, &url, kind, mount.as_deref()).await {
+            Ok(status) => status,
+            Err(e) => {
+                tracing::warn!("{}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = record_track(&pool, &bus, status.title, status.artist, status.listeners).await {
+            tracing::warn!("Failed to record now playing: {}", e);
+            continue;
+        }
+        if let Some(listeners) = status.listeners
+            && let Err(e) = record_listeners(&pool, &bus, listeners).await
+        {
+            tracing::warn!("Failed to record listeners: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::Json;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    #[test]
+    fn parses_icecast_single_and_multiple_sources() {
+        let single = json!({ "icestats": { "source": {
+            "listenurl": "http://radio:8000/live", "listeners": 12, "title": "Artist - Song"
+        }}});
+        assert_eq!(
+            parse_icecast(&single, None).unwrap(),
+            StreamStatus { title: Some("Song".into()), artist: Some("Artist".into()), listeners: Some(12) }
+        );
+
+        let several = json!({ "icestats": { "source": [
+            { "listenurl": "http://radio:8000/backup", "listeners": 1, "title": "Other" },
+            { "listenurl": "http://radio:8000/live", "listeners": 5, "artist": "Band", "title": "A - B" },
+        ]}});
+        let live = parse_icecast(&several, Some("/live")).unwrap();
+        // An explicit artist means the title is taken as-is.
+        assert_eq!(live, StreamStatus { title: Some("A - B".into()), artist: Some("Band".into()), listeners: Some(5) });
+        assert_eq!(parse_icecast(&several, None).unwrap().title.as_deref(), Some("Other"));
+    }
+
+    #[test]
+    fn icecast_without_the_mount_is_off_air() {
+        let off_air = StreamStatus { title: None, artist: None, listeners: Some(0) };
+        assert_eq!(parse_icecast(&json!({ "icestats": {} }), None).unwrap(), off_air);
+
+        let other = json!({ "icestats": { "source": { "listenurl": "http://radio:8000/backup", "title": "X" }}});
+        assert_eq!(parse_icecast(&other, Some("/live")).unwrap(), off_air);
+
+        assert!(parse_icecast(&json!({}), None).is_err());
+    }
+
+    #[test]
+    fn icecast_falls_back_to_yp_currently_playing() {
+        let body = json!({ "icestats": { "source": { "title": "  ", "yp_currently_playing": "Solo" }}});
+        let status = parse_icecast(&body, None).unwrap();
+        assert_eq!((status.artist, status.title, status.listeners), (None, Some("Solo".into()), None));
+    }
+
+    #[test]
+    fn parses_shoutcast_stats() {
+        let body = json!({ "currentlisteners": 7, "streamstatus": 1, "songtitle": "Artist - Song" });
+        assert_eq!(
+            parse_shoutcast(&body).unwrap(),
+            StreamStatus { title: Some("Song".into()), artist: Some("Artist".into()), listeners: Some(7) }
+        );
+
+        let offline = json!({ "currentlisteners": 0, "streamstatus": 0, "songtitle": "Stale - Track" });
+        assert_eq!(parse_shoutcast(&offline).unwrap(), StreamStatus { title: None, artist: None, listeners: Some(0) });
+
+        assert!(parse_shoutcast(&json!({ "songtitle": "x" })).is_err());
+    }
+
+    async fn next_track(events: &mut tokio::sync::broadcast::Receiver<Event>, title: &str) -> NowPlaying {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(Event::NowPlaying(now_playing)) = events.recv().await
+                    && now_playing.title.as_deref() == Some(title)
+                {
+                    return now_playing;
+                }
+            }
+        })
+        .await
+        .expect("poller did not publish the track")
+    }
+
+    #[tokio::test]
+    async fn poller_records_track_changes_from_the_stream_server() {
+        // `None` makes the stub answer 503, which the poller must ride out.
+        let body: Arc<Mutex<Option<Value>>> = Arc::default();
+        let app = axum::Router::new()
+            .route(
+                "/stats",
+                axum::routing::get(|State(body): State<Arc<Mutex<Option<Value>>>>| async move {
+                    body.lock().unwrap().clone().map(Json).ok_or(StatusCode::SERVICE_UNAVAILABLE)
+                }),
+            )
+            .with_state(body.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stats", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let pool = test_pool().await;
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        let poller = tokio::spawn(run_poller(
+            pool,
+            bus,
+            url,
+            ServerKind::Shoutcast,
+            None,
+            Duration::from_millis(100),
+        ));
+
+        let first = format!("Song {}", Uuid::new_v4());
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        *body.lock().unwrap() = Some(json!({ "currentlisteners": 3, "songtitle": format!("Artist - {}", first) }));
+        let now_playing = next_track(&mut events, &first).await;
+        assert_eq!(now_playing.artist.as_deref(), Some("Artist"));
+
+        let second = format!("Song {}", Uuid::new_v4());
+        *body.lock().unwrap() = Some(json!({ "currentlisteners": 4, "songtitle": format!("Artist - {}", second) }));
+        next_track(&mut events, &second).await;
+
+        poller.abort();
+    }
+}